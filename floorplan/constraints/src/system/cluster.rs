@@ -0,0 +1,146 @@
+use super::{ AnchorId, AnchorPoint, Constraint, ConstraintId, ConstraintSystem, PinMode, SecondaryMap, SlotMap, SolverSettings, Vec2 };
+
+// Anchors that are connected to each other through constraints. Clusters
+// don't share anything, so each one can be solved on its own.
+pub(crate) struct Cluster {
+    pub anchors : Vec<AnchorId>,
+    pub constraints : Vec<ConstraintId>,
+}
+
+// Union-find over the anchors
+struct DisjointSet {
+    parent : SecondaryMap<AnchorId, AnchorId>,
+}
+
+impl DisjointSet {
+    fn find( &mut self, id : AnchorId ) -> AnchorId {
+        let mut root = id;
+        while self.parent[ root ] != root {
+            root = self.parent[ root ];
+        }
+
+        // point everything on the way straight at the root
+        let mut curr = id;
+        while curr != root {
+            let next = self.parent[ curr ];
+            self.parent[ curr ] = root;
+            curr = next;
+        }
+        root
+    }
+
+    fn union( &mut self, a : AnchorId, b : AnchorId ) {
+        let root_a = self.find( a );
+        let root_b = self.find( b );
+        if root_a != root_b {
+            self.parent[ root_b ] = root_a;
+        }
+    }
+}
+
+// Anchors without any constraints on them get a cluster of their own, with
+// nothing to solve
+pub(crate) fn find_clusters( csys : &ConstraintSystem ) -> Vec<Cluster> {
+    let mut set = DisjointSet {
+        parent : csys.anchors.keys().map( |id| (id, id) ).collect(),
+    };
+
+    for cons in csys.constraints.values() {
+        let mut first = None;
+        cons.for_each_anchor( |anc| match first {
+            Some( first ) => set.union( first, anc ),
+            None => first = Some( anc ),
+        });
+    }
+
+    let mut clusters : Vec<Cluster> = Vec::new();
+    let mut cluster_ndx : SecondaryMap<AnchorId, usize> = SecondaryMap::new();
+    for (cons_id, cons) in csys.constraints.iter() {
+        // a constraint on nothing (e.g. equal lengths with every pair removed) has nothing to solve
+        let mut first = None;
+        cons.for_each_anchor( |anc| { first.get_or_insert( anc ); } );
+        let Some( first ) = first else {
+            continue;
+        };
+
+        let root = set.find( first );
+        let ndx = *cluster_ndx.entry( root ).unwrap().or_insert_with( || {
+            clusters.push( Cluster { anchors : Vec::new(), constraints : Vec::new() } );
+            clusters.len() - 1
+        });
+        clusters[ ndx ].constraints.push( cons_id );
+    }
+
+    for id in csys.anchors.keys() {
+        let root = set.find( id );
+        let ndx = *cluster_ndx.entry( root ).unwrap().or_insert_with( || {
+            clusters.push( Cluster { anchors : Vec::new(), constraints : Vec::new() } );
+            clusters.len() - 1
+        });
+        clusters[ ndx ].anchors.push( id );
+    }
+
+    clusters
+}
+
+// What every anchor and constraint looked like the last time the cluster they're
+// in finished solving. If a cluster still looks exactly like that, solving it
+// again wouldn't change anything.
+#[derive(Clone, Default)]
+pub(crate) struct SettledCache {
+    anchors : SecondaryMap<AnchorId, (Vec2, PinMode)>,
+    constraints : SecondaryMap<ConstraintId, Constraint>,
+
+    // the settings the clusters were settled with, changing them means solving again
+    settings : SolverSettings,
+}
+
+impl SettledCache {
+
+    pub fn is_settled( &self, csys : &ConstraintSystem, cluster : &Cluster ) -> bool {
+        self.has_settings( &csys.settings ) &&
+        cluster.anchors.iter().all( |id| {
+            let anc = &csys.anchors[ *id ];
+            self.anchors.get( *id ) == Some( &(anc.p, anc.pin) )
+        }) &&
+        cluster.constraints.iter().all( |id| {
+            self.constraints.get( *id ) == Some( &csys.constraints[ *id ] )
+        })
+    }
+
+    pub fn has_settings( &self, settings : &SolverSettings ) -> bool {
+        self.settings == *settings
+    }
+
+    // Forgets everything if the solver settings changed
+    pub fn check_settings( &mut self, settings : &SolverSettings ) {
+        if self.settings != *settings {
+            *self = SettledCache { settings : settings.clone(), ..Default::default() };
+        }
+    }
+
+    pub fn settle( &mut self, anchors : &SlotMap<AnchorId, AnchorPoint>, constraints : &SlotMap<ConstraintId, Constraint>,
+                   cluster : &Cluster ) {
+        for id in cluster.anchors.iter() {
+            let anc = &anchors[ *id ];
+            self.anchors.insert( *id, (anc.p, anc.pin) );
+        }
+        for id in cluster.constraints.iter() {
+            self.constraints.insert( *id, constraints[ *id ].clone() );
+        }
+    }
+
+    // Unsettles the cluster the anchor is in, whatever it is now
+    pub fn forget( &mut self, id : AnchorId ) {
+        self.anchors.remove( id );
+    }
+
+    pub fn unsettle( &mut self, cluster : &Cluster ) {
+        for id in cluster.anchors.iter() {
+            self.anchors.remove( *id );
+        }
+        for id in cluster.constraints.iter() {
+            self.constraints.remove( *id );
+        }
+    }
+}
@@ -0,0 +1,409 @@
+use super::{ AnchorId, ConstraintStatus, ConstraintSystem, DofReport, DofState, PinMode, SecondaryMap };
+use super::cluster::find_clusters;
+use super::lm::{ to_dvec2, to_f64, Residual, Variables };
+use glam::DVec2;
+
+// Degrees of freedom analysis. The constraints are linearized at the current
+// anchor positions, and we look at the rank of the jacobian. A constraint whose
+// rows don't add to the rank is already implied by the ones before it, so it's
+// either redundant (and satisfied) or conflicting (and can't be).
+//
+// Whatever the constraints leave free is the remaining degrees of freedom,
+// except for moving or spinning the whole plan when nothing pins it down,
+// which no amount of dimensioning can fix.
+//
+// Constraints only tie together anchors in the same cluster, so the jacobian
+// is block diagonal and each cluster is looked at on its own. Within a cluster
+// the variables are numbered the same way least squares numbers them, so the
+// rows stay in a narrow band and the rank comes from a banded factorization.
+
+// rows that keep less than this much of their length after removing the
+// part the rows before them already cover are dependent. This is pretty loose,
+// since the gradients come from positions that are only solved to tolerance.
+const RANK_EPSILON : f64 = 1e-4;
+
+fn dot( a : &[f64], b : &[f64] ) -> f64 {
+    a.iter().zip( b.iter() ).map( |(x, y)| x * y ).sum()
+}
+
+// Orthonormal basis, built up one vector at a time
+#[derive(Default)]
+struct Basis {
+    vecs : Vec<Vec<f64>>,
+}
+
+impl Basis {
+
+    // Adds v to the basis if it's independent of what's already there,
+    // returns whether it was added
+    fn add( &mut self, mut v : Vec<f64> ) -> bool {
+        let len = dot( &v, &v ).sqrt();
+        if len < 1e-12 {
+            return false;
+        }
+        v.iter_mut().for_each( |x| *x /= len );
+
+        // gram-schmidt, twice since once isn't enough to stay orthogonal
+        for _ in 0..2 {
+            for b in self.vecs.iter() {
+                let d = dot( &v, b );
+                v.iter_mut().zip( b.iter() ).for_each( |(x, bx)| *x -= d * bx );
+            }
+        }
+
+        let len = dot( &v, &v ).sqrt();
+        if len < RANK_EPSILON {
+            return false;
+        }
+        v.iter_mut().for_each( |x| *x /= len );
+        self.vecs.push( v );
+        true
+    }
+}
+
+// Motions of the whole plan that are still possible with the current pins.
+// Sliding is stopped by any pin in that direction. Turning moves each anchor
+// square to where it is from the center, so it only leaves an x pin alone if
+// the center is level with it, and a y pin if the center is straight above or
+// below it. A single pinned anchor can still spin around itself, two (or a
+// couple of x pins at different heights) can't.
+fn rigid_motions( csys : &ConstraintSystem, vars : &Variables ) -> Vec<Vec<f64>> {
+    let n = vars.count;
+    let pinned_x : Vec<DVec2> = csys.anchors.values().filter( |anc| matches!( anc.pin, PinMode::PinX | PinMode::PinXY ) )
+                                    .map( |anc| to_dvec2( anc.p ) ).collect();
+    let pinned_y : Vec<DVec2> = csys.anchors.values().filter( |anc| matches!( anc.pin, PinMode::PinY | PinMode::PinXY ) )
+                                    .map( |anc| to_dvec2( anc.p ) ).collect();
+
+    let mut motions = Vec::new();
+    if pinned_x.is_empty() {
+        let mut v = vec![ 0.0; n ];
+        vars.index.values().filter_map( |ndx| ndx[0] ).for_each( |i| v[i] = 1.0 );
+        motions.push( v );
+    }
+    if pinned_y.is_empty() {
+        let mut v = vec![ 0.0; n ];
+        vars.index.values().filter_map( |ndx| ndx[1] ).for_each( |i| v[i] = 1.0 );
+        motions.push( v );
+    }
+    if csys.anchors.is_empty() {
+        return motions;
+    }
+
+    // rotation about the middle of the plan, or wherever the pins allow
+    let mid = csys.anchors.values().map( |anc| to_dvec2( anc.p ) ).sum::<DVec2>() / csys.anchors.len() as f64;
+    let ctr = DVec2::new( pinned_y.first().map_or( mid.x, |p| p.x ), pinned_x.first().map_or( mid.y, |p| p.y ) );
+
+    let tolerance = to_f64( csys.settings.tolerance );
+    let can_turn = pinned_x.iter().all( |p| (p.y - ctr.y).abs() <= tolerance ) &&
+                   pinned_y.iter().all( |p| (p.x - ctr.x).abs() <= tolerance );
+    if can_turn {
+        let mut v = vec![ 0.0; n ];
+        for (id, ndx) in vars.index.iter() {
+            let r = to_dvec2( csys.anchors[ id ].p ) - ctr;
+            if let Some( i ) = ndx[0] { v[i] = -r.y; }
+            if let Some( i ) = ndx[1] { v[i] = r.x; }
+        }
+        motions.push( v );
+    }
+    motions
+}
+
+// Upper triangular factor of a set of rows, built up a row at a time with
+// givens rotations. Row j of the factor has its first nonzero in column j,
+// or is missing if none of the rows ended up starting there. Each row is
+// only stored from there to its last nonzero, which stays inside the band.
+struct Triangle {
+    rows : Vec<Option<Vec<f64>>>,
+}
+
+impl Triangle {
+
+    fn new( n : usize ) -> Self {
+        Triangle { rows : vec![ None; n ] }
+    }
+
+    fn rank( &self ) -> usize {
+        self.rows.iter().flatten().count()
+    }
+
+    // Rotates a sparse row into the factor, returns whether it was
+    // independent of the rows already there
+    fn add( &mut self, row : &[(usize, f64)] ) -> bool {
+        let len = row.iter().map( |(_, x)| x * x ).sum::<f64>().sqrt();
+        if len < 1e-12 {
+            return false;
+        }
+
+        let mut w = vec![ 0.0; self.rows.len() ];
+        for (i, x) in row.iter() {
+            w[ *i ] = x / len;
+        }
+
+        let mut hi = w.iter().rposition( |x| *x != 0.0 ).map_or( 0, |i| i + 1 );
+        for j in 0..self.rows.len() {
+            if j >= hi {
+                break;
+            }
+            let x = w[j];
+            match &mut self.rows[j] {
+                Some( r ) if x != 0.0 => {
+                    // zero w[j] against the row that starts there
+                    hi = hi.max( j + r.len() );
+                    r.resize( hi - j, 0.0 );
+                    let h = r[0].hypot( x );
+                    let (c, s) = (r[0] / h, x / h);
+                    for (rk, wk) in r.iter_mut().zip( w[ j..hi ].iter_mut() ) {
+                        let (a, b) = (*rk, *wk);
+                        *rk = c * a + s * b;
+                        *wk = c * b - s * a;
+                    }
+                }
+                None if x.abs() >= RANK_EPSILON => {
+                    self.rows[j] = Some( w[ j..hi ].to_vec() );
+                    return true;
+                }
+                // anything smaller is rounding left over from the rotations, and
+                // if that's all there is the row is dependent
+                _ => {}
+            }
+        }
+        false
+    }
+
+    // Basis for the null space, one vector for each column no row starts in.
+    // That column is one, the other empty columns are zero, and the rest is
+    // back substitution.
+    fn null_space( &self ) -> Vec<Vec<f64>> {
+        let n = self.rows.len();
+        (0..n).filter( |f| self.rows[ *f ].is_none() ).map( |f| {
+            let mut z = vec![ 0.0; n ];
+            z[f] = 1.0;
+            for j in (0..f).rev() {
+                if let Some( r ) = &self.rows[j] {
+                    let s : f64 = r[ 1.. ].iter().zip( z[ j + 1.. ].iter() ).map( |(a, b)| a * b ).sum();
+                    z[j] = -s / r[0];
+                }
+            }
+            z
+        }).collect()
+    }
+}
+
+// One cluster, with variables of its own
+struct Block {
+    // the plan-wide index of each of the cluster's variables
+    vars : Vec<usize>,
+
+    // the cluster's variables for each of its anchors
+    anchor_vars : Vec<[Option<usize>; 2]>,
+
+    triangle : Triangle,
+    rows : Vec<Vec<(usize, f64)>>,
+}
+
+impl Block {
+    // Just the part of a plan-wide vector that's in this cluster
+    fn restrict( &self, v : &[f64] ) -> Vec<f64> {
+        self.vars.iter().map( |i| v[ *i ] ).collect()
+    }
+}
+
+// A residual's row of the jacobian, with each variable in it once
+fn jacobian_row( r : &Residual, vars : &Variables ) -> Vec<(usize, f64)> {
+    let mut row : Vec<(usize, f64)> = Vec::new();
+    for (anc, g) in r.grad.iter() {
+        let ndx = vars.index[ *anc ];
+        for (i, x) in ndx.iter().zip( [ g.x, g.y ] ) {
+            let Some( i ) = i else {
+                continue;
+            };
+            match row.iter_mut().find( |(j, _)| j == i ) {
+                Some( (_, sum) ) => *sum += to_f64( x ),
+                None => row.push( (*i, to_f64( x )) ),
+            }
+        }
+    }
+    row
+}
+
+pub(crate) fn analyze( csys : &ConstraintSystem ) -> DofReport {
+
+    let vars = Variables::new( &csys.anchors );
+    let clusters = find_clusters( csys );
+
+    let mut report = DofReport::default();
+
+    // Walk each cluster's constraints in order, adding their jacobian rows to its factor
+    let mut residuals : Vec<Residual> = Vec::new();
+    let mut blocks : Vec<Block> = Vec::new();
+    for cluster in clusters.iter() {
+        let (sub, sub_ids) = csys.extract_cluster( cluster );
+        let mut sub_vars = Variables::new( &sub.anchors );
+        sub_vars.reorder( &sub );
+
+        let mut block = Block {
+            vars : vec![ 0; sub_vars.count ],
+            anchor_vars : sub_ids.iter().map( |id| sub_vars.index[ *id ] ).collect(),
+            triangle : Triangle::new( sub_vars.count ),
+            rows : Vec::new(),
+        };
+        for (id, sub_id) in cluster.anchors.iter().zip( sub_ids.iter() ) {
+            for (g, l) in vars.index[ *id ].iter().zip( sub_vars.index[ *sub_id ].iter() ) {
+                if let (Some( g ), Some( l )) = (g, l) {
+                    block.vars[ *l ] = *g;
+                }
+            }
+        }
+
+        // the cluster's copy has the constraints in the same order
+        for (id, cons) in cluster.constraints.iter().zip( sub.constraints.values() ) {
+
+            // a range that's comfortably satisfied isn't holding anything in place
+            if cons.is_inactive( &sub.anchors ) {
+                report.constraint_status.insert( *id, ConstraintStatus::Inactive );
+                continue;
+            }
+
+            residuals.clear();
+            cons.residuals( &sub.anchors, &mut residuals );
+
+            let mut dependent = false;
+            for r in residuals.iter() {
+                let row = jacobian_row( r, &sub_vars );
+
+                // a row with no free variables in it at all (e.g. between two pinned anchors)
+                // is dependent too, it can't take away any freedom
+                if !block.triangle.add( &row ) {
+                    dependent = true;
+                }
+                block.rows.push( row );
+            }
+
+            let satisfied = residuals.iter().all( |r| r.value.abs() <= csys.settings.tolerance );
+            let status = match (dependent, satisfied) {
+                (false, _) => ConstraintStatus::Independent,
+                (true, true) => ConstraintStatus::Redundant,
+                (true, false) => ConstraintStatus::Conflicting,
+            };
+            report.constraint_status.insert( *id, status );
+        }
+        blocks.push( block );
+    }
+
+    // a constraint on no anchors at all isn't in any cluster, and has nothing to depend on
+    for id in csys.constraints.keys() {
+        if !report.constraint_status.contains_key( id ) {
+            report.constraint_status.insert( id, ConstraintStatus::Independent );
+        }
+    }
+
+    // The rigid motions the constraints don't stop aren't counted. They move
+    // every cluster at once, so they're the one thing that isn't per cluster.
+    let mut rigid = Basis::default();
+    for motion in rigid_motions( csys, &vars ) {
+        let len = dot( &motion, &motion ).sqrt();
+        let in_null_space = len > 0.0 && blocks.iter().all( |block| {
+            let motion = block.restrict( &motion );
+            block.rows.iter().all( |row| {
+                let row_len = row.iter().map( |(_, x)| x * x ).sum::<f64>().sqrt();
+                let along : f64 = row.iter().map( |(i, x)| x * motion[ *i ] ).sum();
+                row_len == 0.0 || along.abs() < RANK_EPSILON * row_len * len
+            })
+        });
+        if in_null_space && rigid.add( motion ) {
+            report.rigid_modes += 1;
+        }
+    }
+
+    let rank : usize = blocks.iter().map( |block| block.triangle.rank() ).sum();
+    report.dof = vars.count - rank - report.rigid_modes;
+
+    for (cluster, block) in clusters.iter().zip( blocks.iter() ) {
+        let n = block.vars.len();
+
+        // Only the combinations of rigid motions that leave every other cluster
+        // where it is can be taken out of this one's freedom. With the rigid
+        // motions orthonormal, those are the ones the cluster has all of.
+        let motions : Vec<Vec<f64>> = rigid.vecs.iter().map( |v| block.restrict( v ) ).collect();
+        let m = motions.len();
+        let mut combos = Basis::default();
+        for a in 0..m {
+            let row : Vec<f64> = (0..m).map( |b| {
+                let identity = if a == b { 1.0 } else { 0.0 };
+                identity - dot( &motions[a], &motions[b] )
+            }).collect();
+            if dot( &row, &row ).sqrt() > RANK_EPSILON {
+                combos.add( row );
+            }
+        }
+        let first_combo = combos.vecs.len();
+        for a in 0..m {
+            let mut e = vec![ 0.0; m ];
+            e[a] = 1.0;
+            combos.add( e );
+        }
+
+        let mut free_space = Basis::default();
+        for c in combos.vecs[ first_combo.. ].iter() {
+            let mut v = vec![ 0.0; n ];
+            for (ca, motion) in c.iter().zip( motions.iter() ) {
+                v.iter_mut().zip( motion.iter() ).for_each( |(x, mx)| *x += ca * mx );
+            }
+            free_space.add( v );
+        }
+
+        // Whatever's left of the null space after the rigid motions is free to move
+        let fixed = free_space.vecs.len();
+        for z in block.triangle.null_space() {
+            free_space.add( z );
+        }
+        let free = &free_space.vecs[ fixed.. ];
+
+        // An anchor's freedom is how many independent directions the free motions move it in
+        for (id, ndx) in cluster.anchors.iter().zip( block.anchor_vars.iter() ) {
+            let mut anchor_space = Basis::default();
+            let mut dof = 0;
+            for i in ndx.iter().flatten() {
+                let v : Vec<f64> = free.iter().map( |f| f[ *i ] ).collect();
+                if dot( &v, &v ).sqrt() > RANK_EPSILON && anchor_space.add( v ) {
+                    dof += 1;
+                }
+            }
+            report.anchor_dof.insert( *id, dof );
+        }
+    }
+
+    // Roll it up into under/fully/over constrained
+    let mut over_anchors : SecondaryMap<AnchorId, ()> = SecondaryMap::new();
+    for (id, cons) in csys.constraints.iter() {
+        if report.constraint_status[ id ].is_dependent() {
+            for anc in cons.anchors() {
+                over_anchors.insert( anc, () );
+            }
+        }
+    }
+
+    for id in csys.anchors.keys() {
+        let state = if report.anchor_dof[ id ] > 0 {
+            DofState::Under
+        } else if over_anchors.contains_key( id ) {
+            DofState::Over
+        } else {
+            DofState::Full
+        };
+        report.anchor_state.insert( id, state );
+    }
+
+    for (id, cons) in csys.constraints.iter() {
+        let state = if report.constraint_status[ id ].is_dependent() {
+            DofState::Over
+        } else if cons.anchors().iter().any( |anc| report.anchor_dof[ *anc ] > 0 ) {
+            DofState::Under
+        } else {
+            DofState::Full
+        };
+        report.constraint_state.insert( id, state );
+    }
+
+    report
+}
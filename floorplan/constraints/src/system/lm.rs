@@ -0,0 +1,386 @@
+use super::{ AnchorId, AnchorPoint, ConstraintSystem, PinMode, Real, SecondaryMap, SlotMap, Vec2 };
+use glam::DVec2;
+
+// Levenberg-Marquardt backend for the constraint system. Every constraint
+// contributes one or more residuals, and we look for the anchor positions
+// that drive them all to zero, as a damped least squares problem.
+//
+// Each constraint only touches a few anchors, so the normal equations are
+// mostly zeros. The variables are numbered so connected anchors end up close
+// together (reverse Cuthill-McKee), which keeps the nonzeros in a narrow band
+// around the diagonal, and only that band is stored and factored. That's
+// O(n b^2) per iteration for n variables and bandwidth b, instead of O(n^3).
+// A plan that's a long chain of rooms has a narrow band, a big square grid of
+// them (b around 2 sqrt(n)) is the worst case.
+
+const LAMBDA_MIN : f64 = 1e-9;
+const LAMBDA_MAX : f64 = 1e12;
+
+// A single residual of a constraint, along with its gradient with respect to
+// the anchors it touches. The same anchor may appear more than once.
+pub(crate) struct Residual {
+    pub value : Real,
+    pub grad : Vec<(AnchorId, Vec2)>,
+}
+
+// The solver always works in f64, whatever the anchors are stored in (so for
+// constraints::f64 this does nothing)
+#[allow(clippy::useless_conversion)]
+pub(crate) fn to_f64( x : Real ) -> f64 {
+    f64::from( x )
+}
+
+pub(crate) fn to_dvec2( v : Vec2 ) -> DVec2 {
+    DVec2::new( to_f64( v.x ), to_f64( v.y ) )
+}
+
+// Maps each anchor coordinate to its variable index, pinned coordinates
+// aren't variables.
+pub(crate) struct Variables {
+    pub index : SecondaryMap<AnchorId, [Option<usize>; 2]>,
+    pub count : usize,
+}
+
+impl Variables {
+    pub fn new( anchors : &SlotMap<AnchorId, AnchorPoint> ) -> Self {
+        let mut count = 0;
+        let index = anchors.iter().map( |(id, anc)| {
+            let mut next = |free : bool| {
+                if free {
+                    count += 1;
+                    Some( count - 1 )
+                } else {
+                    None
+                }
+            };
+
+            let free_x = anc.pin == PinMode::Unpinned || anc.pin == PinMode::PinY;
+            let free_y = anc.pin == PinMode::Unpinned || anc.pin == PinMode::PinX;
+            (id, [ next( free_x ), next( free_y ) ])
+        }).collect();
+
+        Variables { index, count }
+    }
+
+    // Renumbers the variables in reverse Cuthill-McKee order, breadth first
+    // from a loosely connected one. Variables that share a constraint end up
+    // with nearby numbers, so the normal equations have a narrow band.
+    pub(crate) fn reorder( &mut self, csys : &ConstraintSystem ) {
+        let n = self.count;
+
+        let mut neighbours : Vec<Vec<usize>> = vec![ Vec::new(); n ];
+        let mut vars : Vec<usize> = Vec::new();
+        for cons in csys.constraints.values() {
+            vars.clear();
+            for anc in cons.anchors() {
+                vars.extend( self.index[ anc ].iter().flatten() );
+            }
+            for &i in vars.iter() {
+                neighbours[ i ].extend( vars.iter().filter( |&&j| j != i ) );
+            }
+        }
+        for adj in neighbours.iter_mut() {
+            adj.sort_unstable();
+            adj.dedup();
+        }
+
+        // least connected first, each connected group of variables in turn
+        let mut by_degree : Vec<usize> = (0..n).collect();
+        by_degree.sort_by_key( |&i| neighbours[ i ].len() );
+
+        let mut order = Vec::with_capacity( n );
+        let mut visited = vec![ false; n ];
+        for &start in by_degree.iter() {
+            if visited[ start ] {
+                continue;
+            }
+            visited[ start ] = true;
+            let mut head = order.len();
+            order.push( start );
+            while head < order.len() {
+                let curr = order[ head ];
+                head += 1;
+
+                let mut next : Vec<usize> = neighbours[ curr ].iter().copied().filter( |&j| !visited[ j ] ).collect();
+                next.sort_by_key( |&j| neighbours[ j ].len() );
+                for j in next {
+                    visited[ j ] = true;
+                    order.push( j );
+                }
+            }
+        }
+
+        let mut renumber = vec![ 0; n ];
+        for (new, old) in order.into_iter().rev().enumerate() {
+            renumber[ old ] = new;
+        }
+        for ndx in self.index.values_mut() {
+            for i in ndx.iter_mut().flatten() {
+                *i = renumber[ *i ];
+            }
+        }
+    }
+
+    fn gather( &self, anchors : &SlotMap<AnchorId, AnchorPoint> ) -> Vec<f64> {
+        let mut x = vec![ 0.0; self.count ];
+        for (id, ndx) in self.index.iter() {
+            let p = to_dvec2( anchors[ id ].p );
+            if let Some( i ) = ndx[0] { x[i] = p.x; }
+            if let Some( i ) = ndx[1] { x[i] = p.y; }
+        }
+        x
+    }
+
+    fn scatter( &self, x : &[f64], anchors : &mut SlotMap<AnchorId, AnchorPoint> ) {
+        for (id, ndx) in self.index.iter() {
+            let anc = &mut anchors[ id ];
+            if let Some( i ) = ndx[0] { anc.p.x = x[i] as Real; }
+            if let Some( i ) = ndx[1] { anc.p.y = x[i] as Real; }
+        }
+    }
+}
+
+// Sum of squared residuals weighted by priority (and uncertainty), and the largest (unweighted) residual.
+// The residuals are left unweighted, with the weight of each one in 'weights'.
+fn eval_cost( csys : &ConstraintSystem, anchors : &SlotMap<AnchorId, AnchorPoint>,
+              residuals : &mut Vec<Residual>, weights : &mut Vec<f64> ) -> (f64, f64) {
+    residuals.clear();
+    weights.clear();
+    let mut max_r = 0.0;
+    let mut cost = 0.0;
+    for cons in csys.constraints.values() {
+        let start = residuals.len();
+        cons.residuals( anchors, residuals );
+
+        let weight = cons.weight();
+        for r in residuals[ start.. ].iter() {
+            let value = to_f64( r.value );
+            max_r = f64::max( max_r, value.abs() );
+            cost += (value * weight) * (value * weight);
+            weights.push( weight );
+        }
+    }
+
+    (cost, max_r)
+}
+
+// Symmetric matrix, storing just the lower triangle of each row from its first
+// nonzero up to the diagonal (the envelope). Factoring it doesn't fill in
+// anything outside the envelope.
+#[derive(Clone)]
+struct BandedMatrix {
+    first : Vec<usize>, // column of the first stored entry in each row
+    start : Vec<usize>, // where each row starts in vals
+    vals : Vec<f64>,
+}
+
+impl BandedMatrix {
+
+    // Big enough for every pair of variables that share a residual
+    fn new( n : usize, vars : &Variables, residuals : &[Residual] ) -> Self {
+        let mut first : Vec<usize> = (0..n).collect();
+        let mut row : Vec<usize> = Vec::new();
+        for r in residuals.iter() {
+            row.clear();
+            for (anc, _) in r.grad.iter() {
+                row.extend( vars.index[ *anc ].iter().flatten() );
+            }
+            if let Some( &lowest ) = row.iter().min() {
+                for &i in row.iter() {
+                    first[ i ] = first[ i ].min( lowest );
+                }
+            }
+        }
+
+        let mut start = Vec::with_capacity( n + 1 );
+        let mut len = 0;
+        for (i, f) in first.iter().enumerate() {
+            start.push( len );
+            len += i - f + 1;
+        }
+        start.push( len );
+
+        BandedMatrix { first, start, vals : vec![ 0.0; len ] }
+    }
+
+    fn size( &self ) -> usize {
+        self.first.len()
+    }
+
+    // Entry (i, j) of the lower triangle, j <= i and inside the envelope
+    fn at( &mut self, i : usize, j : usize ) -> &mut f64 {
+        &mut self.vals[ self.start[ i ] + j - self.first[ i ] ]
+    }
+
+    fn diagonal( &self, i : usize ) -> f64 {
+        self.vals[ self.start[ i + 1 ] - 1 ]
+    }
+
+    // Lower triangle of row i, from column first[i]
+    fn row( &self, i : usize ) -> &[f64] {
+        &self.vals[ self.start[ i ]..self.start[ i + 1 ] ]
+    }
+
+    // Solves A x = b in place with a cholesky factorization (A = L L^T, L replaces A).
+    // Returns false if A isn't positive definite.
+    fn cholesky_solve( &mut self, b : &mut [f64] ) -> bool {
+        let n = self.size();
+
+        for i in 0..n {
+            let fi = self.first[ i ];
+            for j in fi..=i {
+                // dot product of rows i and j of L, over the columns they both have
+                let fj = self.first[ j ];
+                let k0 = fi.max( fj );
+                let (ri, rj) = (self.row( i ), self.row( j ));
+                let s : f64 = (k0..j).map( |k| ri[ k - fi ] * rj[ k - fj ] ).sum();
+                let v = ri[ j - fi ] - s;
+
+                if j < i {
+                    let d = self.diagonal( j );
+                    *self.at( i, j ) = v / d;
+                } else {
+                    if v <= 0.0 || !v.is_finite() {
+                        return false;
+                    }
+                    *self.at( i, i ) = v.sqrt();
+                }
+            }
+        }
+
+        // forward substitution, L y = b
+        for i in 0..n {
+            let fi = self.first[ i ];
+            let s : f64 = self.row( i )[ ..i - fi ].iter().zip( b[ fi..i ].iter() ).map( |(l, y)| l * y ).sum();
+            b[i] = (b[i] - s) / self.diagonal( i );
+        }
+
+        // back substitution, L^T x = y, a column of L^T at a time
+        for i in (0..n).rev() {
+            let fi = self.first[ i ];
+            b[i] /= self.diagonal( i );
+            let xi = b[i];
+            for (l, y) in self.row( i )[ ..i - fi ].iter().zip( b[ fi..i ].iter_mut() ) {
+                *y -= l * xi;
+            }
+        }
+
+        true
+    }
+}
+
+// Builds J^T J and J^T r from the residuals, without ever building J itself
+fn normal_equations( vars : &Variables, residuals : &[Residual], weights : &[f64] ) -> (BandedMatrix, Vec<f64>) {
+    let n = vars.count;
+    let mut jtj = BandedMatrix::new( n, vars, residuals );
+    let mut jtr = vec![ 0.0; n ];
+
+    let mut row : Vec<(usize, f64)> = Vec::new();
+    for (r, &weight) in residuals.iter().zip( weights.iter() ) {
+
+        // sparse row of the jacobian for this residual
+        row.clear();
+        for (anc, g) in r.grad.iter() {
+            let g = to_dvec2( *g ) * weight;
+            let ndx = vars.index[ *anc ];
+            if let Some( i ) = ndx[0] { row.push( (i, g.x) ); }
+            if let Some( i ) = ndx[1] { row.push( (i, g.y) ); }
+        }
+
+        for &(i, gi) in row.iter() {
+            jtr[i] += gi * to_f64( r.value ) * weight;
+            for &(j, gj) in row.iter() {
+                if j <= i {
+                    *jtj.at( i, j ) += gi * gj;
+                }
+            }
+        }
+    }
+
+    (jtj, jtr)
+}
+
+// returns the number of iterations taken
+pub(crate) fn solve( csys : &mut ConstraintSystem ) -> usize {
+
+    let mut vars = Variables::new( &csys.anchors );
+    let n = vars.count;
+    if n == 0 || csys.constraints.is_empty() {
+        return 0;
+    }
+    vars.reorder( csys );
+
+    // solve a bit past the tolerance, so the report is comfortably within it
+    let tolerance = to_f64( csys.settings.tolerance ) * 0.1;
+
+    let mut anchors = csys.anchors.clone();
+    let mut x = vars.gather( &anchors );
+    let mut residuals = Vec::new();
+    let mut weights = Vec::new();
+    let mut lambda = f64::clamp( to_f64( csys.settings.lm_lambda ), LAMBDA_MIN, LAMBDA_MAX );
+
+    let (mut cost, mut max_r) = eval_cost( csys, &anchors, &mut residuals, &mut weights );
+
+    let mut iterations = 0;
+    while iterations < csys.settings.lm_max_iterations {
+        if max_r < tolerance {
+            break;
+        }
+        iterations += 1;
+
+        let (jtj, jtr) = normal_equations( &vars, &residuals, &weights );
+
+        // The damping is the same for every variable (scaled to the size of J^T J),
+        // so underconstrained anchors move as little as possible instead of
+        // whichever ones happen to be loosely coupled.
+        let damping_scale = f64::max( (0..n).map( |i| jtj.diagonal( i ) ).sum::<f64>() / n as f64, 1e-9 );
+
+        // Look for a step that lowers the cost, increasing the damping until we find one
+        let mut improved = false;
+        let mut stalled = false;
+        while lambda < LAMBDA_MAX {
+            let mut a = jtj.clone();
+            for i in 0..n {
+                *a.at( i, i ) += lambda * damping_scale;
+            }
+
+            let mut step : Vec<f64> = jtr.iter().map( |g| -g ).collect();
+            if !a.cholesky_solve( &mut step ) {
+                lambda *= 10.0;
+                continue;
+            }
+
+            let x_new : Vec<f64> = x.iter().zip( step.iter() ).map( |(xi, si)| xi + si ).collect();
+            vars.scatter( &x_new, &mut anchors );
+            let (cost_new, max_r_new) = eval_cost( csys, &anchors, &mut residuals, &mut weights );
+
+            if cost_new < cost {
+                // soft constraints that can't be satisfied keep the cost from reaching
+                // zero, stop once it's barely moving
+                stalled = cost - cost_new < cost * 1e-9;
+
+                x = x_new;
+                cost = cost_new;
+                max_r = max_r_new;
+                lambda = f64::max( lambda * 0.1, LAMBDA_MIN );
+                improved = true;
+                break;
+            }
+
+            lambda *= 10.0;
+        }
+
+        if !improved || stalled {
+            // can't make any more progress, we're in a local minimum
+            break;
+        }
+    }
+
+    vars.scatter( &x, &mut anchors );
+    for (id, anc) in csys.anchors.iter_mut() {
+        anc.p = anchors[ id ].p;
+    }
+
+    iterations
+}
+
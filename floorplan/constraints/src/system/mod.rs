@@ -0,0 +1,2277 @@
+// The solver itself. It's compiled once for each scalar type, see lib.rs, and
+// takes Real, Vec2 and consts from whichever module includes it.
+use super::{ consts, AnchorId, ConstraintId, Real, SecondaryMap, SlotMap, Vec2 };
+
+mod lm;
+use lm::Residual;
+
+mod dof;
+
+mod cluster;
+use cluster::{ Cluster, SettledCache };
+
+mod survey;
+
+#[cfg(test)]
+mod tests;
+
+// TODO make this a bitfield?
+#[derive(Copy,Clone,PartialEq)]
+pub enum PinMode
+{
+    Unpinned,
+    PinX,
+    PinY,
+    PinXY,
+}
+
+impl PinMode
+{
+    // Pinned in every axis either one is pinned in
+    pub fn combine( self, other : PinMode ) -> PinMode {
+        let pin_x = matches!( self, PinMode::PinX | PinMode::PinXY ) || matches!( other, PinMode::PinX | PinMode::PinXY );
+        let pin_y = matches!( self, PinMode::PinY | PinMode::PinXY ) || matches!( other, PinMode::PinY | PinMode::PinXY );
+        match (pin_x, pin_y) {
+            (true, true) => PinMode::PinXY,
+            (true, false) => PinMode::PinX,
+            (false, true) => PinMode::PinY,
+            (false, false) => PinMode::Unpinned,
+        }
+    }
+}
+
+#[derive(Copy,Clone)]
+pub struct AnchorPoint
+{
+    pub p : Vec2,
+    pub p_orig : Vec2,
+    pub pin : PinMode,
+}
+
+// Which solver eval_system uses. Relaxation nudges the anchors a little bit
+// every frame, LevenbergMarquardt solves the constraints as a damped least squares
+// problem so dimensioned plans actually hit their targets.
+#[derive(Copy,Clone,PartialEq,Debug,Default)]
+pub enum SolverBackend
+{
+    #[default]
+    Relaxation,
+    LevenbergMarquardt,
+}
+
+// How hard the solver tries to satisfy a constraint. When they can't all be
+// satisfied, softer constraints give way to harder ones.
+#[derive(Copy,Clone,PartialEq,Debug,Default)]
+pub enum Priority
+{
+    #[default]
+    Hard,
+    Strong,
+    Medium,
+    Weak,
+}
+
+impl Priority
+{
+    pub const ALL : [Priority; 4] = [ Priority::Hard, Priority::Strong, Priority::Medium, Priority::Weak ];
+
+    pub fn name( self ) -> &'static str {
+        match self {
+            Priority::Hard => "Hard",
+            Priority::Strong => "Strong",
+            Priority::Medium => "Medium",
+            Priority::Weak => "Weak",
+        }
+    }
+
+    // Scales the relaxation step. Relaxation settles where the pulls balance
+    // out, so a weak constraint still costs a hard one about 1% of the difference.
+    fn strength( self ) -> Real {
+        match self {
+            Priority::Hard => 1.0,
+            Priority::Strong => 0.25,
+            Priority::Medium => 0.05,
+            Priority::Weak => 0.01,
+        }
+    }
+
+    // Scales the least squares residuals. These are squared in the cost, so a
+    // hard constraint outweighs a weak one a million to one. That's more than
+    // an f32 can hold on to, so the solver applies them in f64.
+    fn weight( self ) -> f64 {
+        match self {
+            Priority::Hard => 1000.0,
+            Priority::Strong => 100.0,
+            Priority::Medium => 10.0,
+            Priority::Weak => 1.0,
+        }
+    }
+}
+
+// Order relaxation applies the constraints in. GaussSeidel applies each one
+// straight away so the next one sees it, which converges faster. Jacobi works
+// out every correction from the same positions and averages them, which
+// doesn't depend on the order the constraints were added in.
+#[derive(Copy,Clone,PartialEq,Debug,Default)]
+pub enum UpdateOrder
+{
+    #[default]
+    GaussSeidel,
+    Jacobi,
+}
+
+#[derive(Clone,PartialEq,Debug)]
+pub struct SolverSettings
+{
+    pub backend : SolverBackend,
+
+    // the system is converged once every hard constraint is within this
+    pub tolerance : Real,
+
+    // relaxation steps
+    pub max_iterations : usize,
+
+    // how much of each constraint's error relaxation holds back every step,
+    // 0.98 corrects 2% of it per step
+    pub damping : Real,
+
+    pub order : UpdateOrder,
+
+    // relaxation grows the step while the error is going down and shrinks
+    // it when it isn't, and stops as soon as it converges
+    pub adaptive : bool,
+
+    // least squares iterations, each one is a lot more work than a relaxation step
+    pub lm_max_iterations : usize,
+
+    // The damping least squares starts with. This isn't like the relaxation damping,
+    // it's the lambda added to the normal equations: small takes full Gauss-Newton
+    // steps, large takes short gradient descent steps. It's adjusted as the solve
+    // goes, so it mostly affects the first few iterations.
+    pub lm_lambda : Real,
+}
+
+impl Default for SolverSettings {
+    fn default() -> Self {
+        Self {
+            backend : SolverBackend::default(),
+            tolerance : 1e-3,
+            max_iterations : 100,
+            damping : 0.98,
+            order : UpdateOrder::default(),
+            adaptive : false,
+            lm_max_iterations : 50,
+            lm_lambda : 1e-3,
+        }
+    }
+}
+
+// What kind of error a constraint residual measures
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum ResidualKind
+{
+    Length,   // in world units
+    Angle,    // in radians
+    Parallel, // angle between the walls, in radians
+    Area,     // area difference over the square root of the target area, in world units
+}
+
+#[derive(Copy,Clone,Debug)]
+pub struct ConstraintResidual
+{
+    pub constraint : ConstraintId,
+    pub kind : ResidualKind,
+    pub error : Real,
+}
+
+// Result of eval_system. A system with no constraints is trivially converged.
+// Only the hard constraints have to be within tolerance to count as converged,
+// the softer ones are allowed to give way.
+#[derive(Clone,Debug,Default)]
+pub struct SolveReport
+{
+    // one per constraint, or one per kind for constraints with more than one
+    pub residuals : Vec<ConstraintResidual>,
+    pub max_residual : Real,
+    pub rms_residual : Real,
+    pub iterations : usize,
+    pub converged : bool,
+
+    // constraints whose anchors are on top of each other, so the direction
+    // they'd push in isn't defined. Relaxation skips these (or nudges the
+    // anchors apart), least squares just doesn't get anything out of them.
+    pub degenerate : Vec<ConstraintId>,
+}
+
+// Walls shorter than this don't have a usable direction
+const DEGENERATE_LENGTH : Real = 1e-4;
+
+// How constrained an anchor or constraint is
+#[derive(Copy,Clone,PartialEq,Debug,Default)]
+pub enum DofState
+{
+    #[default]
+    Under, // can still move without breaking anything
+    Full,
+    Over,  // has (or is) a redundant or conflicting constraint
+}
+
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum ConstraintStatus
+{
+    Independent,
+    Redundant,   // implied by the other constraints
+    Conflicting, // implied by the other constraints, and disagrees with them
+    Inactive,    // a range that's satisfied, so it isn't constraining anything right now
+}
+
+impl ConstraintStatus
+{
+    // Redundant or conflicting, i.e. over-constraining
+    pub fn is_dependent( self ) -> bool {
+        matches!( self, ConstraintStatus::Redundant | ConstraintStatus::Conflicting )
+    }
+}
+
+// Result of analyze_dof. Moving or rotating the whole plan doesn't count as
+// a degree of freedom, those are counted in rigid_modes instead.
+#[derive(Clone,Debug,Default)]
+pub struct DofReport
+{
+    pub dof : usize,
+    pub rigid_modes : usize,
+    pub anchor_dof : SecondaryMap<AnchorId, usize>,
+    pub anchor_state : SecondaryMap<AnchorId, DofState>,
+    pub constraint_status : SecondaryMap<ConstraintId, ConstraintStatus>,
+    pub constraint_state : SecondaryMap<ConstraintId, DofState>,
+}
+
+impl DofReport
+{
+    pub fn anchor_state( &self, id : AnchorId ) -> DofState {
+        self.anchor_state.get( id ).copied().unwrap_or_default()
+    }
+
+    pub fn constraint_state( &self, id : ConstraintId ) -> DofState {
+        self.constraint_state.get( id ).copied().unwrap_or_default()
+    }
+
+    pub fn num_redundant( &self ) -> usize {
+        self.constraint_status.values().filter( |s| **s == ConstraintStatus::Redundant ).count()
+    }
+
+    pub fn num_conflicting( &self ) -> usize {
+        self.constraint_status.values().filter( |s| **s == ConstraintStatus::Conflicting ).count()
+    }
+}
+
+// How far a tape measurement is from the plan that best fits all of them
+#[derive(Copy,Clone,Debug)]
+pub struct MeasurementResidual
+{
+    pub constraint : ConstraintId,
+    pub measured : Real,
+    pub fitted : Real,
+
+    // fitted - measured, in world units
+    pub residual : Real,
+
+    // residual in units of the measurement's sigma
+    pub normalized : Real,
+}
+
+impl MeasurementResidual
+{
+    // Further off than the tape could plausibly be
+    pub fn suspect( &self ) -> bool {
+        self.normalized.abs() > 3.0
+    }
+}
+
+// Result of fit_survey. The measurements are sorted worst first, by
+// normalized residual.
+#[derive(Clone,Debug,Default)]
+pub struct SurveyReport
+{
+    pub measurements : Vec<MeasurementResidual>,
+    pub rms_normalized : Real,
+    pub iterations : usize,
+}
+
+impl SurveyReport
+{
+    // The measurement that agrees least with the rest, the one to re-measure first
+    pub fn worst( &self ) -> Option<&MeasurementResidual> {
+        self.measurements.first()
+    }
+}
+
+#[derive(Clone)]
+pub struct ConstraintSystem
+{
+    pub anchors : SlotMap<AnchorId, AnchorPoint>,
+
+    // these don't need to be pub, (and probably shouldn't be),
+    // but I need to access them to draw the constraints.
+    pub constraints : SlotMap<ConstraintId, Constraint>,
+
+    pub settings : SolverSettings,
+
+    // clusters that were solved and haven't changed since don't get solved again
+    settled : SettledCache,
+
+    // bumped whenever the system changes, see revision()
+    revision : u64,
+
+    // set whenever the system changes, see needs_solve()
+    dirty : bool,
+}
+
+// A cluster being solved by eval_system, on a copy of its own
+struct PendingCluster<'a> {
+    cluster : &'a Cluster,
+    sub : ConstraintSystem,
+    sub_ids : Vec<AnchorId>, // the handles for cluster.anchors in the copy
+    iterations : usize,
+    converged : bool,
+    done : bool,
+}
+
+impl Default for ConstraintSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+
+impl ConstraintSystem
+{
+    pub fn new() -> Self {
+        Self {
+            anchors: SlotMap::with_key(),
+            constraints : SlotMap::with_key(),
+            settings : SolverSettings::default(),
+            settled : SettledCache::default(),
+            revision : 0,
+            dirty : false,
+        }
+    }
+
+    // Changes every time the system does, whether it's edited or eval_system moves
+    // something. Anything derived from the system (like analyze_dof) only needs
+    // redoing when this changes.
+    pub fn revision( &self ) -> u64 {
+        self.revision
+    }
+
+    // True if anything changed since the last eval_system (anchors moved or repinned,
+    // constraints added, removed or edited, or the settings changed), or the last
+    // solve hadn't converged yet.
+    pub fn needs_solve( &self ) -> bool {
+        self.dirty || !self.settled.has_settings( &self.settings )
+    }
+
+    // The anchors and constraints can be edited in place, call this afterwards so
+    // the system knows to solve again. Everything else that changes the system
+    // calls it for you.
+    pub fn mark_changed( &mut self ) {
+        self.dirty = true;
+        self.revision += 1;
+    }
+
+    // Replaces the whole system with another one (e.g. a snapshot from the undo
+    // stack). The revision only ever goes up, even though the other system's
+    // could be older, so nothing derived from the system before is mistaken
+    // for being up to date.
+    pub fn copy_from( &mut self, other : ConstraintSystem ) {
+        let revision = self.revision.max( other.revision );
+        *self = other;
+        self.revision = revision;
+        self.mark_changed();
+    }
+
+    pub fn add_anchor( &mut self, p : Vec2 ) -> AnchorId {
+        self.mark_changed();
+        self.anchors.insert( AnchorPoint { p, p_orig : p, pin : PinMode::Unpinned })
+    }
+
+    // Removes an anchor and any constraints that reference it
+    pub fn remove_anchor( &mut self, id : AnchorId ) -> Option<AnchorPoint> {
+        self.unsettle_around( id );
+        let anc = self.anchors.remove( id )?;
+        self.constraints.retain( |_, cons| cons.drop_anchor( id ) );
+        self.mark_changed();
+        Some( anc )
+    }
+
+    // Welds 'remove' into 'keep'. Constraints that used 'remove' are rewired to 'keep',
+    // and dropped if that leaves them degenerate (e.g. a fixed length from an anchor to itself)
+    pub fn merge_anchors( &mut self, keep : AnchorId, remove : AnchorId ) {
+        if keep == remove || !self.anchors.contains_key( keep ) {
+            return;
+        }
+        if !self.anchors.contains_key( remove ) {
+            return;
+        }
+        self.unsettle_around( keep );
+        self.unsettle_around( remove );
+        let removed = self.anchors.remove( remove ).unwrap();
+
+        let anc = &mut self.anchors[ keep ];
+        anc.pin = anc.pin.combine( removed.pin );
+
+        self.constraints.retain( |_, cons| cons.replace_anchor( remove, keep ) );
+        self.mark_changed();
+    }
+
+    pub fn remove_constraint( &mut self, id : ConstraintId ) -> Option<Constraint> {
+        let cons = self.constraints.remove( id )?;
+        cons.for_each_anchor( |anc| self.settled.forget( anc ) );
+        self.mark_changed();
+        Some( cons )
+    }
+
+    // A settled cluster only checks the anchors and constraints it still has, so
+    // taking a constraint away wouldn't unsettle it. This forgets the anchors of
+    // every constraint on the anchor, so they're solved again.
+    fn unsettle_around( &mut self, id : AnchorId ) {
+        for cons in self.constraints.values() {
+            if cons.anchors().contains( &id ) {
+                cons.for_each_anchor( |anc| self.settled.forget( anc ) );
+            }
+        }
+        self.settled.forget( id );
+    }
+
+    fn insert_constraint( &mut self, cons : Constraint ) -> ConstraintId {
+        self.mark_changed();
+        self.constraints.insert( cons )
+    }
+
+    pub fn find_constraint( &self, a : AnchorId, b : AnchorId ) -> Option<Constraint> {
+        self.constraints.values().find( |cc| {
+            match cc {
+                Constraint::FixedLength( cc_fixed ) => {
+                    (cc_fixed.anc_a == a && cc_fixed.anc_b == b) ||
+                    (cc_fixed.anc_a == b && cc_fixed.anc_b == a)
+                }
+                _ => false
+            }
+
+        }).cloned()
+    }
+
+    // If target_len is none, will use the current length between the anchors
+    pub fn add_constraint_fixed_len( &mut self, a : AnchorId, b : AnchorId, target_len : Option<Real> ) -> ConstraintId {
+
+        let target_len = match target_len {
+            Some( len ) => len,
+            None => (self.anchors[ b ].p - self.anchors[ a ].p).length(),
+        };
+
+        self.insert_constraint( Constraint::FixedLength( FixedLengthConstraint { anc_a : a, anc_b : b, target_len, priority : Priority::default() }) )
+    }
+
+    pub fn add_constraint_parallel( &mut self, a : AnchorId, b : AnchorId, c : AnchorId, d : AnchorId ) -> ConstraintId {
+
+        self.insert_constraint(
+            Constraint::Parallel( ParallelConstraint { anc_a : a, anc_b : b, anc_c : c, anc_d : d, priority : Priority::default() } )
+        )
+    }
+
+    // Keeps CD parallel to AB, at distance to the left of it (negative is to the right).
+    // If distance is none, will use the current distance between the walls.
+    pub fn add_constraint_parallel_offset( &mut self, a : AnchorId, b : AnchorId, c : AnchorId, d : AnchorId, distance : Option<Real> ) -> ConstraintId {
+
+        let distance = match distance {
+            Some( dist ) => dist,
+            None => ParallelOffsetConstraint::offset( self.anchors[a].p, self.anchors[b].p, self.anchors[c].p, self.anchors[d].p ),
+        };
+
+        self.insert_constraint(
+            Constraint::ParallelOffset( ParallelOffsetConstraint { anc_a : a, anc_b : b, anc_c : c, anc_d : d, distance, priority : Priority::default() } )
+        )
+    }
+
+    pub fn add_constraint_perpendicular( &mut self, a : AnchorId, b : AnchorId, c : AnchorId, d : AnchorId ) -> ConstraintId {
+
+        self.insert_constraint(
+            Constraint::Perpendicular( PerpendicularConstraint { anc_a : a, anc_b : b, anc_c : c, anc_d : d, priority : Priority::default() } )
+        )
+    }
+
+    pub fn add_constraint_horizontal( &mut self, a : AnchorId, b : AnchorId ) -> ConstraintId {
+        self.insert_constraint( Constraint::Horizontal( HorizontalConstraint { anc_a : a, anc_b : b, priority : Priority::default() } ) )
+    }
+
+    pub fn add_constraint_vertical( &mut self, a : AnchorId, b : AnchorId ) -> ConstraintId {
+        self.insert_constraint( Constraint::Vertical( VerticalConstraint { anc_a : a, anc_b : b, priority : Priority::default() } ) )
+    }
+
+    // Keeps the lengths of all the anchor pairs the same
+    pub fn add_constraint_equal_len( &mut self, pairs : &[(AnchorId, AnchorId)] ) -> ConstraintId {
+        self.insert_constraint( Constraint::EqualLength( EqualLengthConstraint { pairs : pairs.to_vec(), priority : Priority::default() } ) )
+    }
+
+    // Keeps P on the wall from A to B, optionally at a fixed ratio along it
+    pub fn add_constraint_point_on_segment( &mut self, p : AnchorId, a : AnchorId, b : AnchorId, ratio : Option<Real> ) -> ConstraintId {
+        self.insert_constraint( Constraint::PointOnSegment( PointOnSegmentConstraint { anc_p : p, anc_a : a, anc_b : b, ratio, priority : Priority::default() } ) )
+    }
+
+    // A tape measurement between two anchors, give or take sigma. If measured is
+    // none, will use the current length between the anchors.
+    pub fn add_constraint_measurement( &mut self, a : AnchorId, b : AnchorId, measured : Option<Real>, sigma : Real ) -> ConstraintId {
+
+        let measured = match measured {
+            Some( len ) => len,
+            None => (self.anchors[ b ].p - self.anchors[ a ].p).length(),
+        };
+
+        self.insert_constraint( Constraint::Measurement( MeasurementConstraint { anc_a : a, anc_b : b, measured, sigma,
+                                                                                 priority : Priority::Weak }) )
+    }
+
+    // Keeps the distance between A and B within min..max, either can be left open
+    pub fn add_constraint_length_range( &mut self, a : AnchorId, b : AnchorId, min : Option<Real>, max : Option<Real> ) -> ConstraintId {
+        self.insert_constraint( Constraint::LengthRange( LengthRangeConstraint { anc_a : a, anc_b : b, min, max, priority : Priority::default() } ) )
+    }
+
+    // Keeps the angle ABC within min_angle..max_angle, in radians (see AngleConstraint::angle).
+    // The range goes counter-clockwise from min_angle to max_angle, so it can wrap past 0,
+    // e.g. -10 to 10 degrees is the same as 350 to 10.
+    pub fn add_constraint_angle_range( &mut self, a : AnchorId, b : AnchorId, c : AnchorId, min_angle : Real, max_angle : Real ) -> ConstraintId {
+        let min_angle = min_angle.rem_euclid( consts::TAU );
+        let max_angle = max_angle.rem_euclid( consts::TAU );
+        self.insert_constraint( Constraint::AngleRange( AngleRangeConstraint { anc_a : a, anc_b : b, anc_c : c, min_angle, max_angle,
+                                                                               priority : Priority::default() } ) )
+    }
+
+    // Keeps the anchors on one straight line, given in order along it. Any two
+    // anchors are already on a line, so it takes at least three (none otherwise).
+    pub fn add_constraint_collinear( &mut self, anchors : &[AnchorId] ) -> Option<ConstraintId> {
+        if anchors.len() < 3 {
+            return None;
+        }
+        Some( self.insert_constraint( Constraint::Collinear( CollinearConstraint { anchors : anchors.to_vec(), priority : Priority::default() } ) ) )
+    }
+
+    // Locks the anchors together in their current shape, the group can still
+    // move and turn as a whole
+    pub fn add_constraint_rigid( &mut self, anchors : &[AnchorId] ) -> ConstraintId {
+
+        // the first two anchors set the group's frame, it's steadiest with the two furthest apart
+        let mut anchors = anchors.to_vec();
+        if anchors.len() >= 2 {
+            let dist = |a : AnchorId, b : AnchorId| self.anchors[ a ].p.distance( self.anchors[ b ].p );
+            let mut ends = (0, 1);
+            for i in 0..anchors.len() {
+                for j in (i + 1)..anchors.len() {
+                    if dist( anchors[ i ], anchors[ j ] ) > dist( anchors[ ends.0 ], anchors[ ends.1 ] ) {
+                        ends = (i, j);
+                    }
+                }
+            }
+            anchors.swap( 0, ends.0 );
+            anchors.swap( 1, ends.1 );
+        }
+
+        let shape = anchors.iter().map( |a| self.anchors[ *a ].p ).collect();
+        self.insert_constraint( Constraint::Rigid( RigidConstraint { anchors, shape, priority : Priority::default() } ) )
+    }
+
+    // Keeps each pair of anchors mirrored across the axis
+    pub fn add_constraint_symmetry( &mut self, axis : SymmetryAxis, pairs : &[(AnchorId, AnchorId)] ) -> ConstraintId {
+        self.insert_constraint( Constraint::Symmetry( SymmetryConstraint { axis, pairs : pairs.to_vec(), priority : Priority::default() } ) )
+    }
+
+    // Keeps the area of the polygon through the anchors (in order) at target_area.
+    // The area is signed, counter-clockwise loops are positive. If target_area
+    // is none, will use the current area.
+    pub fn add_constraint_area( &mut self, anchors : &[AnchorId], target_area : Option<Real> ) -> ConstraintId {
+        let mut area = AreaConstraint { anchors : anchors.to_vec(), target_area : 0.0, priority : Priority::default() };
+        area.target_area = match target_area {
+            Some( target ) => target,
+            None => area.area( &self.anchors ),
+        };
+
+        self.insert_constraint( Constraint::Area( area ) )
+    }
+
+    pub fn add_constraint_angle( &mut self, a : AnchorId, b : AnchorId, c : AnchorId, target_ang : Option<Real> ) -> ConstraintId
+    {
+        let target_ang = match target_ang {
+            Some( ang ) => ang.rem_euclid( consts::TAU ),
+            None => AngleConstraint::angle( self.anchors[a].p, self.anchors[b].p, self.anchors[c].p ),
+        };
+
+        // println!("Target angle {}", target_ang.to_degrees() );
+
+        self.insert_constraint( Constraint::Angle( AngleConstraint { anc_a : a, anc_b : b, anc_c : c, target_angle : target_ang, priority : Priority::default() }))
+    }
+
+    // Solves each independent cluster of anchors on its own, skipping the ones
+    // that haven't changed since they were last solved. With the "parallel"
+    // feature the clusters are solved on multiple threads.
+    pub fn eval_system( &mut self ) -> SolveReport {
+        self.settled.check_settings( &self.settings );
+        let clusters = cluster::find_clusters( self );
+
+        let mut changed = false;
+        let mut pending : Vec<PendingCluster> = Vec::new();
+        for cluster in clusters.iter() {
+            if self.settled.is_settled( self, cluster ) {
+                continue;
+            }
+
+            changed = true;
+            if cluster.constraints.is_empty() {
+                // a lone anchor, nothing to solve
+                self.settled.settle( &self.anchors, &self.constraints, cluster );
+            } else {
+                let (sub, sub_ids) = self.extract_cluster( cluster );
+                pending.push( PendingCluster { cluster, sub, sub_ids, iterations : 0, converged : false, done : false } );
+            }
+        }
+
+        if changed {
+            self.revision += 1;
+        }
+        self.dirty = false;
+
+        let solve = |pending : &mut PendingCluster| {
+            let sub = &mut pending.sub;
+            let before = sub.anchors.clone();
+            pending.iterations = sub.solve();
+
+            // Done once it's converged, or once solving again wouldn't move anything
+            // (conflicting constraints will never converge)
+            let moved = sub.anchors.iter().map( |(id, anc)| anc.p.distance( before[ id ].p ) ).fold( 0.0, Real::max );
+            pending.converged = sub.residual_report().converged;
+            pending.done = pending.converged || moved < sub.settings.tolerance * 0.01;
+        };
+
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+            pending.par_iter_mut().for_each( solve );
+        }
+        #[cfg(not(feature = "parallel"))]
+        pending.iter_mut().for_each( solve );
+
+        let mut max_iterations = 0;
+        for PendingCluster { cluster, sub, sub_ids, iterations, converged, done } in pending.iter() {
+            for (id, sub_id) in cluster.anchors.iter().zip( sub_ids.iter() ) {
+                // never let a NaN out of the solver, it poisons everything it touches
+                let p = sub.anchors[ *sub_id ].p;
+                if p.is_finite() {
+                    self.anchors[ *id ].p = p;
+                }
+            }
+
+            // Only a converged cluster is settled. One that's stuck stops solving
+            // for now, but gets another go whenever anything in the system changes,
+            // e.g. one of the constraints it's stuck on being removed.
+            if *converged {
+                self.settled.settle( &self.anchors, &self.constraints, cluster );
+            } else {
+                self.settled.unsettle( cluster );
+                if !*done {
+                    self.dirty = true;
+                }
+            }
+            max_iterations = max_iterations.max( *iterations );
+        }
+
+        let mut report = self.residual_report();
+        report.iterations = max_iterations;
+        report
+    }
+
+    // Solves the whole system in one go, returns the number of iterations
+    fn solve( &mut self ) -> usize {
+        match self.settings.backend {
+            SolverBackend::Relaxation => self.eval_relaxation(),
+            SolverBackend::LevenbergMarquardt => lm::solve( self ),
+        }
+    }
+
+    // A copy of the system with just the anchors and constraints in the cluster.
+    // The copy has handles of its own, returned in the same order as
+    // cluster.anchors so the results can be copied back.
+    fn extract_cluster( &self, cluster : &Cluster ) -> (ConstraintSystem, Vec<AnchorId>) {
+        let mut sub = ConstraintSystem { settings : self.settings.clone(), ..ConstraintSystem::new() };
+
+        let mut sub_ids : SecondaryMap<AnchorId, AnchorId> = SecondaryMap::new();
+        let ids = cluster.anchors.iter().map( |id| {
+            let sub_id = sub.anchors.insert( self.anchors[ *id ] );
+            sub_ids.insert( *id, sub_id );
+            sub_id
+        }).collect();
+
+        for id in cluster.constraints.iter() {
+            let mut cons = self.constraints[ *id ].clone();
+            for anc in cons.anchors_mut() {
+                *anc = sub_ids[ *anc ];
+            }
+            sub.constraints.insert( cons );
+        }
+
+        (sub, ids)
+    }
+
+    // Works out how many degrees of freedom the plan has left, per anchor and
+    // overall, and which constraints are redundant or conflicting. This looks at
+    // the current positions, so it's most meaningful after a solve.
+    pub fn analyze_dof( &self ) -> DofReport {
+        dof::analyze( self )
+    }
+
+    // Finds a minimal set of constraints that can't all be satisfied together,
+    // i.e. removing any one of them would let the rest solve. Empty if the
+    // whole system solves. Each candidate set gets a full least squares solve
+    // from the current positions, so this is too slow to run every frame.
+    pub fn find_conflicts( &self ) -> Vec<ConstraintId> {
+        let mut conflicts : Vec<ConstraintId> = self.constraints.keys().collect();
+        if self.solves_with( &conflicts ) {
+            return Vec::new();
+        }
+
+        // Deletion filter, drop each constraint in turn and keep it out if
+        // the rest still can't solve without it
+        let mut ndx = 0;
+        while ndx < conflicts.len() {
+            let removed = conflicts.remove( ndx );
+            if self.solves_with( &conflicts ) {
+                conflicts.insert( ndx, removed );
+                ndx += 1;
+            }
+        }
+
+        conflicts
+    }
+
+    // Can just these constraints be satisfied, starting from the current positions
+    fn solves_with( &self, constraints : &[ConstraintId] ) -> bool {
+        let mut csys = self.clone();
+        csys.constraints.retain( |id, _| constraints.contains( &id ) );
+        lm::solve( &mut csys );
+        csys.residual_report().converged
+    }
+
+    // Measures how well the constraints are currently satisfied, without solving.
+    // Each kind of residual a constraint has is reported on its own (a parallel
+    // offset has an angle and a distance), signed if there's just the one of
+    // that kind, otherwise the magnitude.
+    pub fn residual_report( &self ) -> SolveReport {
+        let mut report = SolveReport::default();
+        let mut residuals = Vec::new();
+        let mut sum_sq = 0.0;
+        let mut max_hard : Real = 0.0;
+
+        for (ndx, cons) in self.constraints.iter() {
+            residuals.clear();
+            cons.residuals( &self.anchors, &mut residuals );
+
+            let mut start = 0;
+            while start < residuals.len() {
+                let kind = cons.residual_kind( start );
+                let end = (start + 1..residuals.len()).find( |i| cons.residual_kind( *i ) != kind ).unwrap_or( residuals.len() );
+
+                let error = if end - start == 1 {
+                    residuals[ start ].value
+                } else {
+                    residuals[ start..end ].iter().map( |r| r.value * r.value ).sum::<Real>().sqrt()
+                };
+                start = end;
+
+                report.max_residual = report.max_residual.max( error.abs() );
+                if cons.priority() == Priority::Hard {
+                    max_hard = max_hard.max( error.abs() );
+                }
+                sum_sq += error * error;
+                report.residuals.push( ConstraintResidual { constraint : ndx, kind, error } );
+            }
+
+            if cons.has_degenerate_geometry( &self.anchors ) {
+                report.degenerate.push( ndx );
+            }
+        }
+
+        if !report.residuals.is_empty() {
+            report.rms_residual = (sum_sq / report.residuals.len() as Real).sqrt();
+        }
+        report.converged = max_hard <= self.settings.tolerance;
+
+        report
+    }
+
+    // returns the number of substeps taken
+    fn eval_relaxation( &mut self ) -> usize {
+        let settings = &self.settings;
+        let base_str = 1.0 - settings.damping;
+
+        // the step can grow up to this when adaptive, any bigger and it starts overshooting
+        let max_str = Real::max( base_str, 0.5 );
+        let check_interval = 10;
+
+        let mut str = base_str;
+        let mut last_error = self.residual_report().max_residual;
+
+        let mut deltas : Vec<(AnchorId, Vec2)> = Vec::new();
+        let mut accum : SecondaryMap<AnchorId, (Vec2, Real)> = SecondaryMap::new();
+
+        for substep in 0..settings.max_iterations {
+
+            // store orig pos
+            for anc in self.anchors.values_mut() {
+                anc.p_orig = anc.p;
+            }
+
+            match settings.order {
+                UpdateOrder::GaussSeidel => {
+                    for cons in self.constraints.values() {
+                        deltas.clear();
+                        cons.relax( &self.anchors, str * cons.priority().strength(), &mut deltas );
+                        deltas.retain( |(_, delta)| delta.is_finite() );
+                        for (id, delta) in deltas.iter() {
+                            self.anchors[ *id ].p += *delta;
+                        }
+                    }
+                }
+
+                UpdateOrder::Jacobi => {
+                    accum.clear();
+                    for cons in self.constraints.values() {
+                        deltas.clear();
+                        cons.relax( &self.anchors, str * cons.priority().strength(), &mut deltas );
+                        deltas.retain( |(_, delta)| delta.is_finite() );
+                        for (id, delta) in deltas.iter() {
+                            let sum = accum.entry( *id ).unwrap().or_insert( (Vec2::ZERO, 0.0) );
+                            sum.0 += *delta;
+                            sum.1 += 1.0;
+                        }
+                    }
+
+                    // average, so anchors with lots of constraints on them don't overshoot
+                    for (id, (delta, count)) in accum.iter() {
+                        self.anchors[ id ].p += *delta / *count;
+                    }
+                }
+            }
+
+            // Apply pins
+            for anc in self.anchors.values_mut() {
+                if anc.pin == PinMode::Unpinned {
+                    continue;
+                }
+
+                anc.p = match anc.pin {
+                    PinMode::PinX => Vec2::new( anc.p_orig.x, anc.p.y ),
+                    PinMode::PinY => Vec2::new( anc.p.x, anc.p_orig.y ),
+                    PinMode::PinXY => anc.p_orig,
+                    _ => unreachable!()
+                };
+            }
+
+            if settings.adaptive && (substep + 1) % check_interval == 0 {
+                let report = self.residual_report();
+                if report.converged {
+                    return substep + 1;
+                }
+
+                str = if report.max_residual < last_error {
+                    Real::min( str * 1.5, max_str )
+                } else {
+                    Real::max( str * 0.5, base_str * 0.1 )
+                };
+                last_error = report.max_residual;
+            }
+        }
+
+        settings.max_iterations
+    }
+
+}
+
+// ====== [ FixedLengthConstraint ]==============================
+// Constrains AB to be the length target_len
+#[derive(Clone,PartialEq)]
+pub struct FixedLengthConstraint {
+    pub anc_a : AnchorId,
+    pub anc_b : AnchorId,
+    pub target_len : Real,
+    pub priority : Priority,
+}
+
+impl FixedLengthConstraint {
+
+    fn eval( &self, anc_a : &mut AnchorPoint, anc_b : &mut AnchorPoint, str : Real  ) {
+        let dir = anc_b.p - anc_a.p;
+        let curr_d = dir.length();
+        let diff = curr_d - self.target_len;
+
+        let dir = dir.normalize_or_zero() * str * 0.5 * diff;
+
+        // modify anchors towards target length
+        anc_a.p += dir;
+        anc_b.p -= dir;
+    }
+
+    // Residual is the difference between the current and target length
+    fn residuals( &self, anchors : &SlotMap<AnchorId, AnchorPoint>, out : &mut Vec<Residual> ) {
+        let dir = anchors[ self.anc_b ].p - anchors[ self.anc_a ].p;
+        let n = dir.normalize_or_zero();
+
+        out.push( Residual {
+            value : dir.length() - self.target_len,
+            grad : vec![ (self.anc_a, -n), (self.anc_b, n) ],
+        });
+    }
+}
+
+// ====== [ MeasurementConstraint ]==============================
+// A measured length of AB, with an uncertainty. Unlike a fixed length, a set of
+// measurements isn't expected to agree exactly, they're fitted as well as they
+// can be (see fit_survey).
+#[derive(Clone,PartialEq)]
+pub struct MeasurementConstraint {
+    pub anc_a : AnchorId,
+    pub anc_b : AnchorId,
+    pub measured : Real,
+
+    // standard deviation of the measurement, in world units
+    pub sigma : Real,
+    pub priority : Priority,
+}
+
+// Smallest sigma we'll weight by, so a zero doesn't blow up the fit
+const MIN_SIGMA : Real = 1e-3;
+
+impl MeasurementConstraint {
+
+    // Relaxation treats it just like a fixed length, sigma only matters to the
+    // least squares fit
+    fn eval( &self, anc_a : &mut AnchorPoint, anc_b : &mut AnchorPoint, str : Real  ) {
+        let fixed_len = FixedLengthConstraint { anc_a : self.anc_a, anc_b : self.anc_b, target_len : self.measured, priority : self.priority };
+        fixed_len.eval( anc_a, anc_b, str );
+    }
+
+    // Residual is the difference between the current and measured length
+    fn residuals( &self, anchors : &SlotMap<AnchorId, AnchorPoint>, out : &mut Vec<Residual> ) {
+        let dir = anchors[ self.anc_b ].p - anchors[ self.anc_a ].p;
+        let n = dir.normalize_or_zero();
+
+        out.push( Residual {
+            value : dir.length() - self.measured,
+            grad : vec![ (self.anc_a, -n), (self.anc_b, n) ],
+        });
+    }
+
+    pub fn length( &self, anchors : &SlotMap<AnchorId, AnchorPoint> ) -> Real {
+        anchors[ self.anc_a ].p.distance( anchors[ self.anc_b ].p )
+    }
+
+    fn weight( &self ) -> Real {
+        1.0 / self.sigma.max( MIN_SIGMA )
+    }
+}
+
+// ============================================
+// Angle helpers, used for the residuals
+
+// Direction of v, in radians
+fn direction_angle( v : Vec2 ) -> Real {
+    v.y.atan2( v.x )
+}
+
+// Wraps an angle into -PI..PI
+fn wrap_angle( ang : Real ) -> Real {
+    let ang = ang.rem_euclid( consts::TAU );
+    if ang > consts::PI {
+        ang - consts::TAU
+    } else {
+        ang
+    }
+}
+
+// Derivative of direction_angle(v) with respect to the endpoint of v
+fn angle_gradient( v : Vec2 ) -> Vec2 {
+    let len_sq = v.length_squared();
+    if len_sq < Real::EPSILON {
+        Vec2::ZERO
+    } else {
+        Vec2::new( -v.y, v.x ) / len_sq
+    }
+}
+
+// ============================================
+// Rotation helpers
+pub trait Vec2RotationHelpers {
+    fn rotate_around_point( &self, center : Vec2, ang_radians : Real ) -> Vec2;
+    fn rotate_around_point_lim( &self, center : Vec2, ang_radians : Real, lim : Real ) -> Vec2;
+}
+impl Vec2RotationHelpers for Vec2 {
+
+    fn rotate_around_point( &self, center : Vec2, ang_radians : Real ) -> Vec2 {
+        let p2 = *self - center;
+        let s = ang_radians.sin();
+        let c = ang_radians.cos();
+        let pr = Vec2::new( p2.x*c - p2.y*s, p2.x*s + p2.y*c );
+
+        // rotated result
+        center + pr
+    }
+
+    fn rotate_around_point_lim( &self, center : Vec2, ang_radians : Real, lim : Real ) -> Vec2 {
+
+        let p2 = self.rotate_around_point( center, ang_radians);
+        if self.distance(p2) < lim {
+            p2
+        } else {
+
+            let dir = (p2 - *self).normalize_or_zero();
+            *self + dir * lim
+        }
+
+    }
+}
+
+
+// ====== [ Parallel Constraint ]==============================
+// Constrains AB to be parallel to CD
+#[derive(Clone,PartialEq)]
+pub struct ParallelConstraint {
+    pub anc_a : AnchorId,
+    pub anc_b : AnchorId,
+    pub anc_c : AnchorId,
+    pub anc_d : AnchorId,
+    pub priority : Priority,
+}
+
+impl ParallelConstraint {
+
+    // might be cleaner to do this by halves? eval AB, and then CD?
+    fn eval( &self,
+        a1 : &mut AnchorPoint, b1 : &mut AnchorPoint,
+        a2 : &mut AnchorPoint, b2 : &mut AnchorPoint,
+        str : Real  ) {
+
+            // this is some weird atan2 syntax
+            let ang1 = ( b1.p.y - a1.p.y).atan2( b1.p.x - a1.p.x );
+            let ang2 = ( b2.p.y - a2.p.y).atan2( b2.p.x - a2.p.x );
+
+            let ang_diff = ang2 - ang1;
+            let ang = ang_diff * 0.5 * str;
+
+            let ctr1 = (a1.p + b1.p) * 0.5;
+            a1.p = a1.p.rotate_around_point( ctr1, ang );
+            b1.p = b1.p.rotate_around_point( ctr1, ang );
+
+            let ctr2 = (a2.p + b2.p) * 0.5;
+            a2.p = a2.p.rotate_around_point( ctr2, -ang );
+            b2.p = b2.p.rotate_around_point( ctr2, -ang );
+    }
+
+    // Residual is the angle between the directions of AB and CD
+    fn residuals( &self, anchors : &SlotMap<AnchorId, AnchorPoint>, out : &mut Vec<Residual> ) {
+        let ab = anchors[ self.anc_b ].p - anchors[ self.anc_a ].p;
+        let cd = anchors[ self.anc_d ].p - anchors[ self.anc_c ].p;
+
+        let g_ab = angle_gradient( ab );
+        let g_cd = angle_gradient( cd );
+
+        out.push( Residual {
+            value : wrap_angle( direction_angle( cd ) - direction_angle( ab ) ),
+            grad : vec![ (self.anc_a, g_ab), (self.anc_b, -g_ab), (self.anc_c, -g_cd), (self.anc_d, g_cd) ],
+        });
+    }
+}
+
+// ====== [ Parallel Offset Constraint ]==============================
+// Constrains CD to be parallel to AB, and distance away from it. The distance
+// is signed, positive is to the left of AB (looking from A to B).
+#[derive(Clone,PartialEq)]
+pub struct ParallelOffsetConstraint {
+    pub anc_a : AnchorId,
+    pub anc_b : AnchorId,
+    pub anc_c : AnchorId,
+    pub anc_d : AnchorId,
+    pub distance : Real,
+    pub priority : Priority,
+}
+
+impl ParallelOffsetConstraint {
+
+    // Signed distance from the line through AB to the middle of CD
+    pub fn offset( pa : Vec2, pb : Vec2, pc : Vec2, pd : Vec2 ) -> Real {
+        let n = (pb - pa).normalize_or_zero().perp();
+        n.dot( (pc + pd) * 0.5 - pa )
+    }
+
+    fn parallel( &self ) -> ParallelConstraint {
+        ParallelConstraint { anc_a : self.anc_a, anc_b : self.anc_b, anc_c : self.anc_c, anc_d : self.anc_d, priority : self.priority }
+    }
+
+    // Straightens the walls out like parallel does, then slides them apart (or together)
+    fn eval( &self,
+        a1 : &mut AnchorPoint, b1 : &mut AnchorPoint,
+        a2 : &mut AnchorPoint, b2 : &mut AnchorPoint,
+        str : Real  ) {
+
+            self.parallel().eval( a1, b1, a2, b2, str );
+
+            let n = (b1.p - a1.p).normalize_or_zero().perp();
+            let diff = Self::offset( a1.p, b1.p, a2.p, b2.p ) - self.distance;
+            let dir = n * diff * str * 0.5;
+
+            a1.p += dir;
+            b1.p += dir;
+            a2.p -= dir;
+            b2.p -= dir;
+    }
+
+    // Residuals are the angle between AB and CD, and how far off the distance is
+    fn residuals( &self, anchors : &SlotMap<AnchorId, AnchorPoint>, out : &mut Vec<Residual> ) {
+        self.parallel().residuals( anchors, out );
+
+        let pa = anchors[ self.anc_a ].p;
+        let pb = anchors[ self.anc_b ].p;
+        let mid_cd = (anchors[ self.anc_c ].p + anchors[ self.anc_d ].p) * 0.5;
+
+        // turning AB around A swings the normal, more the further along AB the middle of CD is
+        let ab = pb - pa;
+        let n = ab.normalize_or_zero().perp();
+        let t = ab.dot( mid_cd - pa ) / ab.length_squared().max( Real::EPSILON );
+
+        out.push( Residual {
+            value : Self::offset( pa, pb, anchors[ self.anc_c ].p, anchors[ self.anc_d ].p ) - self.distance,
+            grad : vec![ (self.anc_a, n * (t - 1.0)), (self.anc_b, -n * t), (self.anc_c, n * 0.5), (self.anc_d, n * 0.5) ],
+        });
+    }
+}
+
+// ====== [ Perpendicular Constraint ]==============================
+// Constrains AB to be perpendicular to CD, either way around
+#[derive(Clone,PartialEq)]
+pub struct PerpendicularConstraint {
+    pub anc_a : AnchorId,
+    pub anc_b : AnchorId,
+    pub anc_c : AnchorId,
+    pub anc_d : AnchorId,
+    pub priority : Priority,
+}
+
+impl PerpendicularConstraint {
+
+    // how far the angle from AB to CD is from the closest right angle
+    fn angle_error( ab : Vec2, cd : Vec2 ) -> Real {
+        let ang_diff = wrap_angle( direction_angle( cd ) - direction_angle( ab ) );
+        if ang_diff < 0.0 {
+            ang_diff + consts::FRAC_PI_2
+        } else {
+            ang_diff - consts::FRAC_PI_2
+        }
+    }
+
+    fn eval( &self,
+        a1 : &mut AnchorPoint, b1 : &mut AnchorPoint,
+        a2 : &mut AnchorPoint, b2 : &mut AnchorPoint,
+        str : Real  ) {
+
+            let ang = Self::angle_error( b1.p - a1.p, b2.p - a2.p ) * 0.5 * str;
+
+            // same as parallel, rotate both walls around their centers
+            let ctr1 = (a1.p + b1.p) * 0.5;
+            a1.p = a1.p.rotate_around_point( ctr1, ang );
+            b1.p = b1.p.rotate_around_point( ctr1, ang );
+
+            let ctr2 = (a2.p + b2.p) * 0.5;
+            a2.p = a2.p.rotate_around_point( ctr2, -ang );
+            b2.p = b2.p.rotate_around_point( ctr2, -ang );
+    }
+
+    fn residuals( &self, anchors : &SlotMap<AnchorId, AnchorPoint>, out : &mut Vec<Residual> ) {
+        let ab = anchors[ self.anc_b ].p - anchors[ self.anc_a ].p;
+        let cd = anchors[ self.anc_d ].p - anchors[ self.anc_c ].p;
+
+        let g_ab = angle_gradient( ab );
+        let g_cd = angle_gradient( cd );
+
+        out.push( Residual {
+            value : Self::angle_error( ab, cd ),
+            grad : vec![ (self.anc_a, g_ab), (self.anc_b, -g_ab), (self.anc_c, -g_cd), (self.anc_d, g_cd) ],
+        });
+    }
+}
+
+// ====== [ Horizontal Constraint ]==============================
+// Constrains AB to be horizontal, A and B end up with the same y
+#[derive(Clone,PartialEq)]
+pub struct HorizontalConstraint {
+    pub anc_a : AnchorId,
+    pub anc_b : AnchorId,
+    pub priority : Priority,
+}
+
+impl HorizontalConstraint {
+
+    fn eval( &self, anc_a : &mut AnchorPoint, anc_b : &mut AnchorPoint, str : Real ) {
+        let diff = (anc_b.p.y - anc_a.p.y) * str * 0.5;
+        anc_a.p.y += diff;
+        anc_b.p.y -= diff;
+    }
+
+    fn residuals( &self, anchors : &SlotMap<AnchorId, AnchorPoint>, out : &mut Vec<Residual> ) {
+        out.push( Residual {
+            value : anchors[ self.anc_b ].p.y - anchors[ self.anc_a ].p.y,
+            grad : vec![ (self.anc_a, Vec2::NEG_Y), (self.anc_b, Vec2::Y) ],
+        });
+    }
+}
+
+// ====== [ Vertical Constraint ]==============================
+// Constrains AB to be vertical, A and B end up with the same x
+#[derive(Clone,PartialEq)]
+pub struct VerticalConstraint {
+    pub anc_a : AnchorId,
+    pub anc_b : AnchorId,
+    pub priority : Priority,
+}
+
+impl VerticalConstraint {
+
+    fn eval( &self, anc_a : &mut AnchorPoint, anc_b : &mut AnchorPoint, str : Real ) {
+        let diff = (anc_b.p.x - anc_a.p.x) * str * 0.5;
+        anc_a.p.x += diff;
+        anc_b.p.x -= diff;
+    }
+
+    fn residuals( &self, anchors : &SlotMap<AnchorId, AnchorPoint>, out : &mut Vec<Residual> ) {
+        out.push( Residual {
+            value : anchors[ self.anc_b ].p.x - anchors[ self.anc_a ].p.x,
+            grad : vec![ (self.anc_a, Vec2::NEG_X), (self.anc_b, Vec2::X) ],
+        });
+    }
+}
+
+// ====== [ EqualLength Constraint ]==============================
+// Constrains the distance between each pair of anchors to be the same,
+// without fixing what that length is
+#[derive(Clone,PartialEq)]
+pub struct EqualLengthConstraint {
+    pub pairs : Vec<(AnchorId, AnchorId)>,
+    pub priority : Priority,
+}
+
+impl EqualLengthConstraint {
+
+    pub fn lengths( &self, anchors : &SlotMap<AnchorId, AnchorPoint> ) -> Vec<Real> {
+        self.pairs.iter().map( |(a, b)| anchors[ *a ].p.distance( anchors[ *b ].p ) ).collect()
+    }
+
+    // pulls every pair towards the average length
+    fn eval( &self, anchors : &SlotMap<AnchorId, AnchorPoint>, str : Real, out : &mut Vec<(AnchorId, Vec2)> ) {
+        if self.pairs.is_empty() {
+            return;
+        }
+
+        let lengths = self.lengths( anchors );
+        let target_len = lengths.iter().sum::<Real>() / lengths.len() as Real;
+
+        for (a, b) in self.pairs.iter() {
+            let dir = anchors[ *b ].p - anchors[ *a ].p;
+            let diff = dir.length() - target_len;
+            let dir = dir.normalize_or_zero() * str * 0.5 * diff;
+
+            out.push( (*a, dir) );
+            out.push( (*b, -dir) );
+        }
+    }
+
+    // One residual for each pair after the first, its length minus the first length
+    fn residuals( &self, anchors : &SlotMap<AnchorId, AnchorPoint>, out : &mut Vec<Residual> ) {
+        let Some( &(a0, b0) ) = self.pairs.first() else {
+            return;
+        };
+
+        let dir0 = anchors[ b0 ].p - anchors[ a0 ].p;
+        let n0 = dir0.normalize_or_zero();
+
+        for (a, b) in self.pairs.iter().skip( 1 ) {
+            let dir = anchors[ *b ].p - anchors[ *a ].p;
+            let n = dir.normalize_or_zero();
+
+            out.push( Residual {
+                value : dir.length() - dir0.length(),
+                grad : vec![ (*a, -n), (*b, n), (a0, n0), (b0, -n0) ],
+            });
+        }
+    }
+}
+
+// ====== [ PointOnSegment Constraint ]==============================
+// Constrains P to lie on the segment AB, between A and B (for T-junctions).
+// If ratio is set, P is also kept at that fraction of the way from A to B.
+#[derive(Clone,PartialEq)]
+pub struct PointOnSegmentConstraint {
+    pub anc_p : AnchorId,
+    pub anc_a : AnchorId,
+    pub anc_b : AnchorId,
+    pub ratio : Option<Real>,
+    pub priority : Priority,
+}
+
+impl PointOnSegmentConstraint {
+
+    // Where P should be, given where it is now
+    fn target( &self, p : Vec2, a : Vec2, b : Vec2 ) -> Vec2 {
+        let ab = b - a;
+        let t = match self.ratio {
+            Some( t ) => t,
+            None => Self::along( p, a, b ).clamp( 0.0, 1.0 ),
+        };
+        a + ab * t
+    }
+
+    // How far along AB the closest point on the line to P is, 0 at A and 1 at B
+    fn along( p : Vec2, a : Vec2, b : Vec2 ) -> Real {
+        let ab = b - a;
+        let len_sq = ab.length_squared();
+        if len_sq < Real::EPSILON { 0.0 } else { (p - a).dot( ab ) / len_sq }
+    }
+
+    fn eval( &self, anc_p : &mut AnchorPoint, anc_a : &mut AnchorPoint, anc_b : &mut AnchorPoint, str : Real ) {
+        let delta = (self.target( anc_p.p, anc_a.p, anc_b.p ) - anc_p.p) * str * 0.5;
+
+        // move P towards the line, and the line towards P
+        anc_p.p += delta;
+        anc_a.p -= delta * 0.5;
+        anc_b.p -= delta * 0.5;
+    }
+
+    fn residuals( &self, anchors : &SlotMap<AnchorId, AnchorPoint>, out : &mut Vec<Residual> ) {
+        let p = anchors[ self.anc_p ].p;
+        let a = anchors[ self.anc_a ].p;
+        let b = anchors[ self.anc_b ].p;
+
+        match self.ratio {
+            Some( t ) => {
+                // P minus its target point, one residual for x and one for y
+                let q = self.target( p, a, b );
+                for axis in [ Vec2::X, Vec2::Y ] {
+                    out.push( Residual {
+                        value : (p - q).dot( axis ),
+                        grad : vec![ (self.anc_p, axis), (self.anc_a, -axis * (1.0 - t)), (self.anc_b, -axis * t) ],
+                    });
+                }
+            }
+            None => {
+                // signed distance from P to the line
+                let ab = b - a;
+                let ap = p - a;
+                let len = ab.length();
+                if len < Real::EPSILON {
+                    return;
+                }
+
+                let dist = ab.perp_dot( ap ) / len;
+                let t = Self::along( p, a, b );
+                if (0.0..=1.0).contains( &t ) {
+                    let g_p = ab.perp() / len;
+                    let g_b = Vec2::new( ap.y, -ap.x ) / len - ab * (dist / (len * len));
+
+                    out.push( Residual {
+                        value : dist,
+                        grad : vec![ (self.anc_p, g_p), (self.anc_a, -(g_p + g_b)), (self.anc_b, g_b) ],
+                    });
+                } else {
+                    // past the end of the wall, so the distance to that end. It takes the
+                    // sign of the distance to the line so the two meet up at the end.
+                    let (end, anc_end) = if t < 0.0 { (a, self.anc_a) } else { (b, self.anc_b) };
+                    let sign = if dist < 0.0 { -1.0 } else { 1.0 };
+                    let n = (p - end).normalize_or_zero() * sign;
+
+                    out.push( Residual {
+                        value : p.distance( end ) * sign,
+                        grad : vec![ (self.anc_p, n), (anc_end, -n) ],
+                    });
+                }
+            }
+        }
+    }
+}
+
+// ====== [ Angle Constraint ]==============================
+// Constrains the angle ABC to be a target angle. The angle is signed, measured
+// counter-clockwise from BA to BC, so it covers reflex corners too and
+// a corner can't flip over and end up mirrored.
+#[derive(Clone,PartialEq)]
+pub struct AngleConstraint {
+    pub anc_a : AnchorId,
+    pub anc_b : AnchorId,
+    pub anc_c : AnchorId,
+    pub target_angle : Real, // in radians, 0..2PI
+    pub priority : Priority,
+}
+
+impl AngleConstraint {
+
+    // Counter-clockwise angle from BA to BC, in 0..2PI
+    pub fn angle( a : Vec2, b : Vec2, c : Vec2 ) -> Real {
+        let ba = a - b;
+        let bc = c - b;
+        ba.perp_dot( bc ).atan2( ba.dot( bc ) ).rem_euclid( consts::TAU )
+    }
+
+    // how far the current angle is from the target, the short way around
+    fn angle_error( &self, a : Vec2, b : Vec2, c : Vec2 ) -> Real {
+        wrap_angle( Self::angle( a, b, c ) - self.target_angle )
+    }
+
+    fn eval( &self,
+        anc_a : &mut AnchorPoint,
+        anc_b : &mut AnchorPoint,
+        anc_c : &mut AnchorPoint,
+         str : Real ) {
+
+            let ang_diff = self.angle_error( anc_a.p, anc_b.p, anc_c.p );
+
+            let ang = ang_diff * 0.5 * str;
+
+            // anc_a.p = anc_a.p.rotate_around_point_lim( anc_b.p, ang, 0.1 );
+            // anc_c.p = anc_c.p.rotate_around_point_lim( anc_b.p, -ang, 0.1 );
+            anc_a.p = anc_a.p.rotate_around_point( anc_b.p, ang );
+            anc_c.p = anc_c.p.rotate_around_point( anc_b.p, -ang );
+
+    }
+
+    // Residual is the signed difference between the angle ABC and the target
+    fn residuals( &self, anchors : &SlotMap<AnchorId, AnchorPoint>, out : &mut Vec<Residual> ) {
+        let pa = anchors[ self.anc_a ].p;
+        let pb = anchors[ self.anc_b ].p;
+        let pc = anchors[ self.anc_c ].p;
+
+        let g_a = -angle_gradient( pa - pb );
+        let g_c = angle_gradient( pc - pb );
+
+        out.push( Residual {
+            value : self.angle_error( pa, pb, pc ),
+            grad : vec![ (self.anc_a, g_a), (self.anc_b, -(g_a + g_c)), (self.anc_c, g_c) ],
+        });
+    }
+}
+
+// ====== [ LengthRange Constraint ]==============================
+// Constrains AB to be at least min and at most max long. Does nothing while
+// the length is in range, and acts like a fixed length at whichever end it's
+// past when it isn't.
+#[derive(Clone,PartialEq)]
+pub struct LengthRangeConstraint {
+    pub anc_a : AnchorId,
+    pub anc_b : AnchorId,
+    pub min : Option<Real>,
+    pub max : Option<Real>,
+    pub priority : Priority,
+}
+
+impl LengthRangeConstraint {
+
+    // The end of the range the length is past, if any
+    fn violated( &self, anchors : &SlotMap<AnchorId, AnchorPoint> ) -> Option<FixedLengthConstraint> {
+        let len = anchors[ self.anc_a ].p.distance( anchors[ self.anc_b ].p );
+        let target_len = match (self.min, self.max) {
+            (Some( min ), _) if len < min => min,
+            (_, Some( max )) if len > max => max,
+            _ => return None,
+        };
+        Some( FixedLengthConstraint { anc_a : self.anc_a, anc_b : self.anc_b, target_len, priority : self.priority } )
+    }
+
+    // The residual is zero (with no gradient) while in range
+    fn residuals( &self, anchors : &SlotMap<AnchorId, AnchorPoint>, out : &mut Vec<Residual> ) {
+        match self.violated( anchors ) {
+            Some( fixed_len ) => fixed_len.residuals( anchors, out ),
+            None => out.push( Residual { value : 0.0, grad : Vec::new() } ),
+        }
+    }
+}
+
+// ====== [ AngleRange Constraint ]==============================
+// Constrains the angle ABC (counter-clockwise from BA to BC, like the angle
+// constraint) to be between min_angle and max_angle. Does nothing while it's
+// in range, and pushes back towards the nearest end when it isn't.
+#[derive(Clone,PartialEq)]
+pub struct AngleRangeConstraint {
+    pub anc_a : AnchorId,
+    pub anc_b : AnchorId,
+    pub anc_c : AnchorId,
+    pub min_angle : Real, // in radians, 0..2PI
+    pub max_angle : Real, // less than min_angle if the range wraps past 0
+    pub priority : Priority,
+}
+
+impl AngleRangeConstraint {
+
+    // How far the range goes counter-clockwise from min_angle
+    pub fn span( &self ) -> Real {
+        (self.max_angle - self.min_angle).rem_euclid( consts::TAU )
+    }
+
+    // An angle constraint for the end of the range the angle is past, if any.
+    // Outside the range could be past either end going the other way around,
+    // so it's whichever is closer.
+    fn violated( &self, anchors : &SlotMap<AnchorId, AnchorPoint> ) -> Option<AngleConstraint> {
+        let ang = AngleConstraint::angle( anchors[ self.anc_a ].p, anchors[ self.anc_b ].p, anchors[ self.anc_c ].p );
+        if (ang - self.min_angle).rem_euclid( consts::TAU ) <= self.span() {
+            return None;
+        }
+
+        let target_angle = if wrap_angle( ang - self.min_angle ).abs() < wrap_angle( ang - self.max_angle ).abs() {
+            self.min_angle
+        } else {
+            self.max_angle
+        };
+        Some( AngleConstraint { anc_a : self.anc_a, anc_b : self.anc_b, anc_c : self.anc_c, target_angle, priority : self.priority } )
+    }
+
+    fn residuals( &self, anchors : &SlotMap<AnchorId, AnchorPoint>, out : &mut Vec<Residual> ) {
+        match self.violated( anchors ) {
+            Some( angle ) => angle.residuals( anchors, out ),
+            None => out.push( Residual { value : 0.0, grad : Vec::new() } ),
+        }
+    }
+}
+
+// ====== [ Collinear Constraint ]==============================
+// Constrains the anchors to a straight line, like a wall that's been split up
+// by doors or junctions. They're in order along the line, and the line goes
+// through the first and last ones.
+#[derive(Clone,PartialEq)]
+pub struct CollinearConstraint {
+    pub anchors : Vec<AnchorId>,
+    pub priority : Priority,
+}
+
+impl CollinearConstraint {
+
+    // Every anchor in between stays on the line between the ends
+    fn on_segments( &self ) -> Vec<PointOnSegmentConstraint> {
+        let [first, middle @ .., last] = self.anchors.as_slice() else {
+            return Vec::new();
+        };
+
+        middle.iter().map( |p| {
+            PointOnSegmentConstraint { anc_p : *p, anc_a : *first, anc_b : *last, ratio : None, priority : self.priority }
+        }).collect()
+    }
+
+    fn eval( &self, anchors : &SlotMap<AnchorId, AnchorPoint>, str : Real, out : &mut Vec<(AnchorId, Vec2)> ) {
+        for on_seg in self.on_segments() {
+            Constraint::PointOnSegment( on_seg ).relax( anchors, str, out );
+        }
+    }
+
+    // One residual for each anchor in between, its distance from the line
+    fn residuals( &self, anchors : &SlotMap<AnchorId, AnchorPoint>, out : &mut Vec<Residual> ) {
+        for on_seg in self.on_segments() {
+            on_seg.residuals( anchors, out );
+        }
+    }
+}
+
+// ====== [ Rigid Constraint ]==============================
+// Locks a group of anchors into a fixed shape (like a finished bathroom), so
+// it can only move and turn as a whole. Rather than a fixed length between every
+// pair, the first two anchors set up a frame, and every other anchor is held at
+// its spot in that frame. That's 2n - 3 residuals for n anchors, which is
+// exactly how much freedom a rigid shape takes away.
+#[derive(Clone,PartialEq)]
+pub struct RigidConstraint {
+    pub anchors : Vec<AnchorId>,
+
+    // where each anchor is in the shape, in any position (only the shape matters)
+    pub shape : Vec<Vec2>,
+    pub priority : Priority,
+}
+
+impl RigidConstraint {
+
+    // Frame from the first two points, origin and x axis
+    fn frame( p0 : Vec2, p1 : Vec2 ) -> (Vec2, Vec2) {
+        (p0, (p1 - p0).normalize_or_zero())
+    }
+
+    // Best fit of the shape to the anchors (procrustes), as the rotation (cos, sin)
+    // and where the middle of the shape ends up. Mapping shape point s to the plan
+    // is rot.rotate( s - shape_center ) + center.
+    pub fn placement( &self, anchors : &SlotMap<AnchorId, AnchorPoint> ) -> (Vec2, Vec2, Vec2) {
+        let n = self.anchors.len().max( 1 ) as Real;
+        let center = self.anchors.iter().map( |a| anchors[ *a ].p ).sum::<Vec2>() / n;
+        let shape_center = self.shape.iter().copied().sum::<Vec2>() / n;
+
+        let (mut cross, mut dot) = (0.0, 0.0);
+        for (a, s) in self.anchors.iter().zip( self.shape.iter() ) {
+            let p = anchors[ *a ].p - center;
+            let s = *s - shape_center;
+            cross += s.perp_dot( p );
+            dot += s.dot( p );
+        }
+
+        (Vec2::from_angle( cross.atan2( dot ) ), shape_center, center)
+    }
+
+    // Moves every anchor towards where the best fit of the shape puts it
+    fn eval( &self, anchors : &SlotMap<AnchorId, AnchorPoint>, str : Real, out : &mut Vec<(AnchorId, Vec2)> ) {
+        let (rot, shape_center, center) = self.placement( anchors );
+        for (a, s) in self.anchors.iter().zip( self.shape.iter() ) {
+            let target = rot.rotate( *s - shape_center ) + center;
+            out.push( (*a, (target - anchors[ *a ].p) * str) );
+        }
+    }
+
+    // The distance between the first two anchors, then where each of the
+    // others is in their frame (along and across)
+    fn residuals( &self, anchors : &SlotMap<AnchorId, AnchorPoint>, out : &mut Vec<Residual> ) {
+        if self.anchors.len() < 2 {
+            return;
+        }
+        let (a0, a1) = (self.anchors[0], self.anchors[1]);
+        let (origin, u) = Self::frame( anchors[ a0 ].p, anchors[ a1 ].p );
+        let (shape_origin, shape_u) = Self::frame( self.shape[0], self.shape[1] );
+        let n = u.perp();
+        let len = anchors[ a0 ].p.distance( anchors[ a1 ].p ).max( Real::EPSILON );
+
+        out.push( Residual {
+            value : len - self.shape[0].distance( self.shape[1] ),
+            grad : vec![ (a0, -u), (a1, u) ],
+        });
+
+        for (a, s) in self.anchors.iter().zip( self.shape.iter() ).skip( 2 ) {
+            let w = anchors[ *a ].p - origin;
+            let (x, y) = (u.dot( w ), n.dot( w ));
+            let s = *s - shape_origin;
+
+            // turning the frame (by moving the second anchor) swings the axes around
+            out.push( Residual {
+                value : x - shape_u.dot( s ),
+                grad : vec![ (*a, u), (a1, n * (y / len)), (a0, -u - n * (y / len)) ],
+            });
+            out.push( Residual {
+                value : y - shape_u.perp().dot( s ),
+                grad : vec![ (*a, n), (a1, -n * (x / len)), (a0, -n + n * (x / len)) ],
+            });
+        }
+    }
+}
+
+// ====== [ Area Constraint ]==============================
+// Constrains the signed area of the polygon through the anchors, in order.
+// Counter-clockwise loops have positive area, so a room can't turn itself
+// inside out to get there.
+#[derive(Clone,PartialEq)]
+pub struct AreaConstraint {
+    pub anchors : Vec<AnchorId>,
+    pub target_area : Real,
+    pub priority : Priority,
+}
+
+impl AreaConstraint {
+
+    // Shoelace formula
+    pub fn area( &self, anchors : &SlotMap<AnchorId, AnchorPoint> ) -> Real {
+        let n = self.anchors.len();
+        (0..n).map( |i| {
+            let p = anchors[ self.anchors[ i ] ].p;
+            let q = anchors[ self.anchors[ (i + 1) % n ] ].p;
+            p.perp_dot( q )
+        }).sum::<Real>() * 0.5
+    }
+
+    // Derivative of the area with respect to each anchor, moving a corner along
+    // this grows the room fastest
+    fn gradients( &self, anchors : &SlotMap<AnchorId, AnchorPoint> ) -> Vec<(AnchorId, Vec2)> {
+        let n = self.anchors.len();
+        (0..n).map( |i| {
+            let prev = anchors[ self.anchors[ (i + n - 1) % n ] ].p;
+            let next = anchors[ self.anchors[ (i + 1) % n ] ].p;
+            (self.anchors[ i ], -(next - prev).perp() * 0.5)
+        }).collect()
+    }
+
+    // area is in square units, so scale it down to about a length, otherwise
+    // it would swamp everything else in the least squares fit
+    fn scale( &self ) -> Real {
+        self.target_area.abs().sqrt().max( 1.0 )
+    }
+
+    // moves every corner along the area gradient, as far as it takes to fix
+    // the area if the gradient didn't change
+    fn eval( &self, anchors : &SlotMap<AnchorId, AnchorPoint>, str : Real, out : &mut Vec<(AnchorId, Vec2)> ) {
+        let grads = self.gradients( anchors );
+        let grad_sq : Real = grads.iter().map( |(_, g)| g.length_squared() ).sum();
+        if grad_sq < Real::EPSILON {
+            return;
+        }
+
+        let step = (self.target_area - self.area( anchors )) / grad_sq * str;
+        out.extend( grads.iter().map( |(id, g)| (*id, *g * step) ) );
+    }
+
+    fn residuals( &self, anchors : &SlotMap<AnchorId, AnchorPoint>, out : &mut Vec<Residual> ) {
+        let scale = self.scale();
+        out.push( Residual {
+            value : (self.area( anchors ) - self.target_area) / scale,
+            grad : self.gradients( anchors ).into_iter().map( |(id, g)| (id, g / scale) ).collect(),
+        });
+    }
+}
+
+// ====== [ Symmetry Constraint ]==============================
+// Constrains each pair of anchors to be mirror images of each other across an
+// axis. An anchor paired with itself is kept on the axis.
+#[derive(Clone,PartialEq)]
+pub struct SymmetryConstraint {
+    pub axis : SymmetryAxis,
+    pub pairs : Vec<(AnchorId, AnchorId)>,
+    pub priority : Priority,
+}
+
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum SymmetryAxis
+{
+    // the line through two anchors, which the solver can move
+    Anchors( AnchorId, AnchorId ),
+
+    // a line that stays put, through point along dir
+    Fixed { point : Vec2, dir : Vec2 },
+}
+
+impl SymmetryConstraint {
+
+    // A point on the axis and its direction (normalized)
+    pub fn axis_line( &self, anchors : &SlotMap<AnchorId, AnchorPoint> ) -> (Vec2, Vec2) {
+        match self.axis {
+            SymmetryAxis::Anchors( a, b ) => (anchors[ a ].p, (anchors[ b ].p - anchors[ a ].p).normalize_or_zero()),
+            SymmetryAxis::Fixed { point, dir } => (point, dir.normalize_or_zero()),
+        }
+    }
+
+    fn mirror( p : Vec2, origin : Vec2, dir : Vec2 ) -> Vec2 {
+        let n = dir.perp();
+        p - n * (2.0 * n.dot( p - origin ))
+    }
+
+    // moves each anchor towards the mirror image of its partner. The axis
+    // stays where it is, the pairs can always line up around it.
+    fn eval( &self, anchors : &SlotMap<AnchorId, AnchorPoint>, str : Real, out : &mut Vec<(AnchorId, Vec2)> ) {
+        let (origin, dir) = self.axis_line( anchors );
+
+        for (a, b) in self.pairs.iter() {
+            let pa = anchors[ *a ].p;
+            let pb = anchors[ *b ].p;
+            out.push( (*a, (Self::mirror( pb, origin, dir ) - pa) * str * 0.5) );
+            out.push( (*b, (Self::mirror( pa, origin, dir ) - pb) * str * 0.5) );
+        }
+    }
+
+    // Two residuals for each pair, how far the middle of the pair is from the
+    // axis, and how far the pair is from being square to it
+    fn residuals( &self, anchors : &SlotMap<AnchorId, AnchorPoint>, out : &mut Vec<Residual> ) {
+        let (origin, dir) = self.axis_line( anchors );
+        let n = dir.perp();
+
+        for (a, b) in self.pairs.iter() {
+            let pa = anchors[ *a ].p;
+            let pb = anchors[ *b ].p;
+            let mid = (pa + pb) * 0.5;
+            let ab = pb - pa;
+
+            let mut grad_mid = vec![ (*a, n * 0.5), (*b, n * 0.5) ];
+            let mut grad_square = vec![ (*a, -dir), (*b, dir) ];
+
+            // an anchor axis moves with its anchors, and turns around the first one
+            if let SymmetryAxis::Anchors( axis_a, axis_b ) = self.axis {
+                let len = anchors[ axis_a ].p.distance( anchors[ axis_b ].p ).max( Real::EPSILON );
+
+                let t = dir.dot( mid - origin ) / len;
+                grad_mid.push( (axis_a, n * (t - 1.0)) );
+                grad_mid.push( (axis_b, -n * t) );
+
+                let s = n.dot( ab ) / len;
+                grad_square.push( (axis_a, -n * s) );
+                grad_square.push( (axis_b, n * s) );
+            }
+
+            out.push( Residual { value : n.dot( mid - origin ), grad : grad_mid } );
+            out.push( Residual { value : dir.dot( ab ), grad : grad_square } );
+        }
+    }
+}
+
+// ============================================
+
+#[derive(Clone,PartialEq)]
+pub enum Constraint {
+    //DummyConstraint,
+    FixedLength( FixedLengthConstraint ),
+    Parallel( ParallelConstraint ),
+    ParallelOffset( ParallelOffsetConstraint ),
+    Perpendicular( PerpendicularConstraint ),
+    Horizontal( HorizontalConstraint ),
+    Vertical( VerticalConstraint ),
+    EqualLength( EqualLengthConstraint ),
+    PointOnSegment( PointOnSegmentConstraint ),
+    Angle( AngleConstraint ),
+    Measurement( MeasurementConstraint ),
+    Symmetry( SymmetryConstraint ),
+    Area( AreaConstraint ),
+    LengthRange( LengthRangeConstraint ),
+    AngleRange( AngleRangeConstraint ),
+    Collinear( CollinearConstraint ),
+    Rigid( RigidConstraint ),
+}
+
+impl Constraint {
+
+    // Appends the residuals (and their gradients) of this constraint, used by the
+    // least squares solver. A satisfied constraint has all residuals at zero.
+    fn residuals( &self, anchors : &SlotMap<AnchorId, AnchorPoint>, out : &mut Vec<Residual> ) {
+        match self {
+            Constraint::FixedLength( fixed_len ) => fixed_len.residuals( anchors, out ),
+            Constraint::Parallel( parallel ) => parallel.residuals( anchors, out ),
+            Constraint::ParallelOffset( offset ) => offset.residuals( anchors, out ),
+            Constraint::Perpendicular( perp ) => perp.residuals( anchors, out ),
+            Constraint::Horizontal( horiz ) => horiz.residuals( anchors, out ),
+            Constraint::Vertical( vert ) => vert.residuals( anchors, out ),
+            Constraint::EqualLength( equal_len ) => equal_len.residuals( anchors, out ),
+            Constraint::PointOnSegment( on_seg ) => on_seg.residuals( anchors, out ),
+            Constraint::Angle( angle ) => angle.residuals( anchors, out ),
+            Constraint::Measurement( measurement ) => measurement.residuals( anchors, out ),
+            Constraint::Symmetry( symmetry ) => symmetry.residuals( anchors, out ),
+            Constraint::Area( area ) => area.residuals( anchors, out ),
+            Constraint::LengthRange( len_range ) => len_range.residuals( anchors, out ),
+            Constraint::AngleRange( ang_range ) => ang_range.residuals( anchors, out ),
+            Constraint::Collinear( collinear ) => collinear.residuals( anchors, out ),
+            Constraint::Rigid( rigid ) => rigid.residuals( anchors, out ),
+        }
+    }
+
+    // Relaxation step for this constraint. Works out how far it wants to move
+    // each anchor from the current positions, without moving them.
+    fn relax( &self, anchors : &SlotMap<AnchorId, AnchorPoint>, str : Real, out : &mut Vec<(AnchorId, Vec2)> ) {
+
+        if self.has_degenerate_geometry( anchors ) {
+            // A fixed length can push its anchors apart in any direction, so pick
+            // one. Nothing else knows which way to go, so leave it until some other
+            // constraint (or the user) moves the anchors apart.
+            let apart = match self {
+                Constraint::FixedLength( fixed_len ) => Some( (fixed_len.anc_a, fixed_len.anc_b, fixed_len.target_len) ),
+                Constraint::Measurement( measurement ) => Some( (measurement.anc_a, measurement.anc_b, measurement.measured) ),
+                Constraint::LengthRange( len_range ) => len_range.min.map( |min| (len_range.anc_a, len_range.anc_b, min) ),
+                _ => None,
+            };
+            if let Some( (anc_a, anc_b, len) ) = apart {
+                let dir = Vec2::X * str * 0.5 * len;
+                out.push( (anc_a, -dir) );
+                out.push( (anc_b, dir) );
+            }
+            return;
+        }
+
+        match self {
+            //Constraint::DummyConstraint => {}
+            Constraint::FixedLength( fixed_len ) => {
+                let mut anc_a = anchors[ fixed_len.anc_a ];
+                let mut anc_b = anchors[ fixed_len.anc_b ];
+                fixed_len.eval( &mut anc_a, &mut anc_b, str  );
+                out.push( (fixed_len.anc_a, anc_a.p - anchors[ fixed_len.anc_a ].p) );
+                out.push( (fixed_len.anc_b, anc_b.p - anchors[ fixed_len.anc_b ].p) );
+            }
+
+            Constraint::Parallel( parallel ) => {
+                let mut anc_a = anchors[ parallel.anc_a ];
+                let mut anc_b = anchors[ parallel.anc_b ];
+                let mut anc_c = anchors[ parallel.anc_c ];
+                let mut anc_d = anchors[ parallel.anc_d ];
+                parallel.eval( &mut anc_a, &mut anc_b, &mut anc_c, &mut anc_d, str  );
+                out.push( (parallel.anc_a, anc_a.p - anchors[ parallel.anc_a ].p) );
+                out.push( (parallel.anc_b, anc_b.p - anchors[ parallel.anc_b ].p) );
+                out.push( (parallel.anc_c, anc_c.p - anchors[ parallel.anc_c ].p) );
+                out.push( (parallel.anc_d, anc_d.p - anchors[ parallel.anc_d ].p) );
+            }
+
+            Constraint::ParallelOffset( offset ) => {
+                let mut anc_a = anchors[ offset.anc_a ];
+                let mut anc_b = anchors[ offset.anc_b ];
+                let mut anc_c = anchors[ offset.anc_c ];
+                let mut anc_d = anchors[ offset.anc_d ];
+                offset.eval( &mut anc_a, &mut anc_b, &mut anc_c, &mut anc_d, str  );
+                out.push( (offset.anc_a, anc_a.p - anchors[ offset.anc_a ].p) );
+                out.push( (offset.anc_b, anc_b.p - anchors[ offset.anc_b ].p) );
+                out.push( (offset.anc_c, anc_c.p - anchors[ offset.anc_c ].p) );
+                out.push( (offset.anc_d, anc_d.p - anchors[ offset.anc_d ].p) );
+            }
+
+            Constraint::Perpendicular( perp ) => {
+                let mut anc_a = anchors[ perp.anc_a ];
+                let mut anc_b = anchors[ perp.anc_b ];
+                let mut anc_c = anchors[ perp.anc_c ];
+                let mut anc_d = anchors[ perp.anc_d ];
+                perp.eval( &mut anc_a, &mut anc_b, &mut anc_c, &mut anc_d, str  );
+                out.push( (perp.anc_a, anc_a.p - anchors[ perp.anc_a ].p) );
+                out.push( (perp.anc_b, anc_b.p - anchors[ perp.anc_b ].p) );
+                out.push( (perp.anc_c, anc_c.p - anchors[ perp.anc_c ].p) );
+                out.push( (perp.anc_d, anc_d.p - anchors[ perp.anc_d ].p) );
+            }
+
+            Constraint::Horizontal( horiz ) => {
+                let mut anc_a = anchors[ horiz.anc_a ];
+                let mut anc_b = anchors[ horiz.anc_b ];
+                horiz.eval( &mut anc_a, &mut anc_b, str );
+                out.push( (horiz.anc_a, anc_a.p - anchors[ horiz.anc_a ].p) );
+                out.push( (horiz.anc_b, anc_b.p - anchors[ horiz.anc_b ].p) );
+            }
+
+            Constraint::Vertical( vert ) => {
+                let mut anc_a = anchors[ vert.anc_a ];
+                let mut anc_b = anchors[ vert.anc_b ];
+                vert.eval( &mut anc_a, &mut anc_b, str );
+                out.push( (vert.anc_a, anc_a.p - anchors[ vert.anc_a ].p) );
+                out.push( (vert.anc_b, anc_b.p - anchors[ vert.anc_b ].p) );
+            }
+
+            Constraint::EqualLength( equal_len ) => {
+                // touches any number of anchors, so this one makes its own deltas
+                equal_len.eval( anchors, str, out );
+            }
+
+            Constraint::PointOnSegment( on_seg ) => {
+                let mut anc_p = anchors[ on_seg.anc_p ];
+                let mut anc_a = anchors[ on_seg.anc_a ];
+                let mut anc_b = anchors[ on_seg.anc_b ];
+                on_seg.eval( &mut anc_p, &mut anc_a, &mut anc_b, str );
+                out.push( (on_seg.anc_p, anc_p.p - anchors[ on_seg.anc_p ].p) );
+                out.push( (on_seg.anc_a, anc_a.p - anchors[ on_seg.anc_a ].p) );
+                out.push( (on_seg.anc_b, anc_b.p - anchors[ on_seg.anc_b ].p) );
+            }
+
+            Constraint::Angle( angle) => {
+                let mut anc_a = anchors[ angle.anc_a ];
+                let mut anc_b = anchors[ angle.anc_b ];
+                let mut anc_c = anchors[ angle.anc_c ];
+                angle.eval( &mut anc_a, &mut anc_b, &mut anc_c, str );
+                out.push( (angle.anc_a, anc_a.p - anchors[ angle.anc_a ].p) );
+                out.push( (angle.anc_b, anc_b.p - anchors[ angle.anc_b ].p) );
+                out.push( (angle.anc_c, anc_c.p - anchors[ angle.anc_c ].p) );
+            }
+
+            Constraint::Measurement( measurement ) => {
+                let mut anc_a = anchors[ measurement.anc_a ];
+                let mut anc_b = anchors[ measurement.anc_b ];
+                measurement.eval( &mut anc_a, &mut anc_b, str );
+                out.push( (measurement.anc_a, anc_a.p - anchors[ measurement.anc_a ].p) );
+                out.push( (measurement.anc_b, anc_b.p - anchors[ measurement.anc_b ].p) );
+            }
+
+            Constraint::Symmetry( symmetry ) => {
+                // any number of pairs, makes its own deltas
+                symmetry.eval( anchors, str, out );
+            }
+
+            Constraint::Area( area ) => {
+                area.eval( anchors, str, out );
+            }
+
+            // only do anything when out of range, then act like the end they're past
+            Constraint::LengthRange( len_range ) => {
+                if let Some( fixed_len ) = len_range.violated( anchors ) {
+                    Constraint::FixedLength( fixed_len ).relax( anchors, str, out );
+                }
+            }
+
+            Constraint::AngleRange( ang_range ) => {
+                if let Some( angle ) = ang_range.violated( anchors ) {
+                    Constraint::Angle( angle ).relax( anchors, str, out );
+                }
+            }
+
+            Constraint::Collinear( collinear ) => {
+                collinear.eval( anchors, str, out );
+            }
+
+            Constraint::Rigid( rigid ) => {
+                rigid.eval( anchors, str, out );
+            }
+        }
+    }
+
+    // True if some wall this constraint measures has collapsed to a point,
+    // so it doesn't have a direction
+    pub fn has_degenerate_geometry( &self, anchors : &SlotMap<AnchorId, AnchorPoint> ) -> bool {
+        let short = |a : AnchorId, b : AnchorId| anchors[ a ].p.distance( anchors[ b ].p ) < DEGENERATE_LENGTH;
+        match self {
+            // a zero target length is happy with coincident anchors
+            Constraint::FixedLength( fixed_len ) => fixed_len.target_len > DEGENERATE_LENGTH && short( fixed_len.anc_a, fixed_len.anc_b ),
+            Constraint::Parallel( parallel ) => short( parallel.anc_a, parallel.anc_b ) || short( parallel.anc_c, parallel.anc_d ),
+            Constraint::ParallelOffset( offset ) => short( offset.anc_a, offset.anc_b ) || short( offset.anc_c, offset.anc_d ),
+            Constraint::Perpendicular( perp ) => short( perp.anc_a, perp.anc_b ) || short( perp.anc_c, perp.anc_d ),
+            Constraint::Horizontal( _ ) | Constraint::Vertical( _ ) => false,
+            Constraint::EqualLength( equal_len ) => equal_len.pairs.iter().any( |(a, b)| short( *a, *b ) ),
+            Constraint::PointOnSegment( on_seg ) => short( on_seg.anc_a, on_seg.anc_b ),
+            Constraint::Angle( angle ) => short( angle.anc_a, angle.anc_b ) || short( angle.anc_c, angle.anc_b ),
+            Constraint::Measurement( measurement ) => measurement.measured > DEGENERATE_LENGTH && short( measurement.anc_a, measurement.anc_b ),
+            Constraint::Symmetry( symmetry ) => match symmetry.axis {
+                SymmetryAxis::Anchors( a, b ) => short( a, b ),
+                SymmetryAxis::Fixed { dir, .. } => dir.length() < DEGENERATE_LENGTH,
+            },
+            // all the corners on top of each other, no way to tell which way is out
+            Constraint::Area( area ) => area.gradients( anchors ).iter().all( |(_, g)| g.length() < DEGENERATE_LENGTH ),
+            Constraint::LengthRange( len_range ) => len_range.min.is_some_and( |min| min > DEGENERATE_LENGTH ) && short( len_range.anc_a, len_range.anc_b ),
+            Constraint::AngleRange( ang_range ) => short( ang_range.anc_a, ang_range.anc_b ) || short( ang_range.anc_c, ang_range.anc_b ),
+            Constraint::Collinear( collinear ) => match (collinear.anchors.first(), collinear.anchors.last()) {
+                (Some( first ), Some( last )) => short( *first, *last ),
+                _ => false,
+            },
+            // the frame needs the first two apart
+            Constraint::Rigid( rigid ) => rigid.anchors.len() >= 2 && short( rigid.anchors[0], rigid.anchors[1] ),
+        }
+    }
+
+    pub fn priority( &self ) -> Priority {
+        match self {
+            Constraint::FixedLength( fixed_len ) => fixed_len.priority,
+            Constraint::Parallel( parallel ) => parallel.priority,
+            Constraint::ParallelOffset( offset ) => offset.priority,
+            Constraint::Perpendicular( perp ) => perp.priority,
+            Constraint::Horizontal( horiz ) => horiz.priority,
+            Constraint::Vertical( vert ) => vert.priority,
+            Constraint::EqualLength( equal_len ) => equal_len.priority,
+            Constraint::PointOnSegment( on_seg ) => on_seg.priority,
+            Constraint::Angle( angle ) => angle.priority,
+            Constraint::Measurement( measurement ) => measurement.priority,
+            Constraint::Symmetry( symmetry ) => symmetry.priority,
+            Constraint::Area( area ) => area.priority,
+            Constraint::LengthRange( len_range ) => len_range.priority,
+            Constraint::AngleRange( ang_range ) => ang_range.priority,
+            Constraint::Collinear( collinear ) => collinear.priority,
+            Constraint::Rigid( rigid ) => rigid.priority,
+        }
+    }
+
+    // How much the least squares solver weights this constraint's residuals.
+    // Measurements are also weighted by how sure we are of them.
+    fn weight( &self ) -> f64 {
+        match self {
+            Constraint::Measurement( measurement ) => self.priority().weight() * lm::to_f64( measurement.weight() ),
+            _ => self.priority().weight(),
+        }
+    }
+
+    pub fn priority_mut( &mut self ) -> &mut Priority {
+        match self {
+            Constraint::FixedLength( fixed_len ) => &mut fixed_len.priority,
+            Constraint::Parallel( parallel ) => &mut parallel.priority,
+            Constraint::ParallelOffset( offset ) => &mut offset.priority,
+            Constraint::Perpendicular( perp ) => &mut perp.priority,
+            Constraint::Horizontal( horiz ) => &mut horiz.priority,
+            Constraint::Vertical( vert ) => &mut vert.priority,
+            Constraint::EqualLength( equal_len ) => &mut equal_len.priority,
+            Constraint::PointOnSegment( on_seg ) => &mut on_seg.priority,
+            Constraint::Angle( angle ) => &mut angle.priority,
+            Constraint::Measurement( measurement ) => &mut measurement.priority,
+            Constraint::Symmetry( symmetry ) => &mut symmetry.priority,
+            Constraint::Area( area ) => &mut area.priority,
+            Constraint::LengthRange( len_range ) => &mut len_range.priority,
+            Constraint::AngleRange( ang_range ) => &mut ang_range.priority,
+            Constraint::Collinear( collinear ) => &mut collinear.priority,
+            Constraint::Rigid( rigid ) => &mut rigid.priority,
+        }
+    }
+
+    // All the anchors this constraint references
+    pub fn anchors( &self ) -> Vec<AnchorId> {
+        let mut anchors = Vec::new();
+        self.for_each_anchor( |anc| anchors.push( anc ) );
+        anchors
+    }
+
+    // Same as anchors(), without building a Vec. Used where it's called for every
+    // constraint in the system.
+    pub(crate) fn for_each_anchor( &self, mut f : impl FnMut( AnchorId ) ) {
+        match self {
+            Constraint::FixedLength( fixed_len ) => [ fixed_len.anc_a, fixed_len.anc_b ].into_iter().for_each( f ),
+            Constraint::Parallel( parallel ) => [ parallel.anc_a, parallel.anc_b, parallel.anc_c, parallel.anc_d ].into_iter().for_each( f ),
+            Constraint::ParallelOffset( offset ) => [ offset.anc_a, offset.anc_b, offset.anc_c, offset.anc_d ].into_iter().for_each( f ),
+            Constraint::Perpendicular( perp ) => [ perp.anc_a, perp.anc_b, perp.anc_c, perp.anc_d ].into_iter().for_each( f ),
+            Constraint::Horizontal( horiz ) => [ horiz.anc_a, horiz.anc_b ].into_iter().for_each( f ),
+            Constraint::Vertical( vert ) => [ vert.anc_a, vert.anc_b ].into_iter().for_each( f ),
+            Constraint::EqualLength( equal_len ) => equal_len.pairs.iter().flat_map( |(a, b)| [ *a, *b ] ).for_each( f ),
+            Constraint::PointOnSegment( on_seg ) => [ on_seg.anc_p, on_seg.anc_a, on_seg.anc_b ].into_iter().for_each( f ),
+            Constraint::Angle( angle ) => [ angle.anc_a, angle.anc_b, angle.anc_c ].into_iter().for_each( f ),
+            Constraint::Measurement( measurement ) => [ measurement.anc_a, measurement.anc_b ].into_iter().for_each( f ),
+            Constraint::Symmetry( symmetry ) => {
+                symmetry.pairs.iter().flat_map( |(a, b)| [ *a, *b ] ).for_each( &mut f );
+                if let SymmetryAxis::Anchors( a, b ) = symmetry.axis {
+                    f( a );
+                    f( b );
+                }
+            }
+            Constraint::Area( area ) => area.anchors.iter().copied().for_each( f ),
+            Constraint::LengthRange( len_range ) => [ len_range.anc_a, len_range.anc_b ].into_iter().for_each( f ),
+            Constraint::AngleRange( ang_range ) => [ ang_range.anc_a, ang_range.anc_b, ang_range.anc_c ].into_iter().for_each( f ),
+            Constraint::Collinear( collinear ) => collinear.anchors.iter().copied().for_each( f ),
+            Constraint::Rigid( rigid ) => rigid.anchors.iter().copied().for_each( f ),
+        }
+    }
+
+    fn anchors_mut( &mut self ) -> Vec<&mut AnchorId> {
+        match self {
+            Constraint::FixedLength( fixed_len ) => vec![ &mut fixed_len.anc_a, &mut fixed_len.anc_b ],
+            Constraint::Parallel( parallel ) => vec![ &mut parallel.anc_a, &mut parallel.anc_b, &mut parallel.anc_c, &mut parallel.anc_d ],
+            Constraint::ParallelOffset( offset ) => vec![ &mut offset.anc_a, &mut offset.anc_b, &mut offset.anc_c, &mut offset.anc_d ],
+            Constraint::Perpendicular( perp ) => vec![ &mut perp.anc_a, &mut perp.anc_b, &mut perp.anc_c, &mut perp.anc_d ],
+            Constraint::Horizontal( horiz ) => vec![ &mut horiz.anc_a, &mut horiz.anc_b ],
+            Constraint::Vertical( vert ) => vec![ &mut vert.anc_a, &mut vert.anc_b ],
+            Constraint::EqualLength( equal_len ) => equal_len.pairs.iter_mut().flat_map( |(a, b)| [ a, b ] ).collect(),
+            Constraint::PointOnSegment( on_seg ) => vec![ &mut on_seg.anc_p, &mut on_seg.anc_a, &mut on_seg.anc_b ],
+            Constraint::Angle( angle ) => vec![ &mut angle.anc_a, &mut angle.anc_b, &mut angle.anc_c ],
+            Constraint::Measurement( measurement ) => vec![ &mut measurement.anc_a, &mut measurement.anc_b ],
+            Constraint::Symmetry( symmetry ) => {
+                let mut anchors : Vec<&mut AnchorId> = symmetry.pairs.iter_mut().flat_map( |(a, b)| [ a, b ] ).collect();
+                if let SymmetryAxis::Anchors( a, b ) = &mut symmetry.axis {
+                    anchors.extend( [ a, b ] );
+                }
+                anchors
+            }
+            Constraint::Area( area ) => area.anchors.iter_mut().collect(),
+            Constraint::LengthRange( len_range ) => vec![ &mut len_range.anc_a, &mut len_range.anc_b ],
+            Constraint::AngleRange( ang_range ) => vec![ &mut ang_range.anc_a, &mut ang_range.anc_b, &mut ang_range.anc_c ],
+            Constraint::Collinear( collinear ) => collinear.anchors.iter_mut().collect(),
+            Constraint::Rigid( rigid ) => rigid.anchors.iter_mut().collect(),
+        }
+    }
+
+    // True if the constraint uses the same anchor in places where it needs two different ones
+    fn is_degenerate( &self ) -> bool {
+        match self {
+            Constraint::FixedLength( fixed_len ) => fixed_len.anc_a == fixed_len.anc_b,
+            Constraint::Parallel( parallel ) => parallel.anc_a == parallel.anc_b || parallel.anc_c == parallel.anc_d,
+            Constraint::ParallelOffset( offset ) => offset.anc_a == offset.anc_b || offset.anc_c == offset.anc_d,
+            Constraint::Perpendicular( perp ) => perp.anc_a == perp.anc_b || perp.anc_c == perp.anc_d,
+            Constraint::Horizontal( horiz ) => horiz.anc_a == horiz.anc_b,
+            Constraint::Vertical( vert ) => vert.anc_a == vert.anc_b,
+            Constraint::EqualLength( equal_len ) => equal_len.pairs.len() < 2,
+            Constraint::PointOnSegment( on_seg ) => on_seg.anc_a == on_seg.anc_b ||
+                on_seg.anc_p == on_seg.anc_a || on_seg.anc_p == on_seg.anc_b,
+            Constraint::Angle( angle ) => angle.anc_a == angle.anc_b || angle.anc_c == angle.anc_b,
+            Constraint::Measurement( measurement ) => measurement.anc_a == measurement.anc_b,
+            Constraint::Symmetry( symmetry ) => symmetry.pairs.is_empty() ||
+                matches!( symmetry.axis, SymmetryAxis::Anchors( a, b ) if a == b ),
+            Constraint::Area( area ) => area.anchors.len() < 3,
+            Constraint::LengthRange( len_range ) => len_range.anc_a == len_range.anc_b,
+            Constraint::AngleRange( ang_range ) => ang_range.anc_a == ang_range.anc_b || ang_range.anc_c == ang_range.anc_b,
+            Constraint::Collinear( collinear ) => collinear.anchors.len() < 3,
+            // merging two anchors of a rigid group would squash the shape, so that's the end of it
+            Constraint::Rigid( rigid ) => rigid.anchors.len() < 2 ||
+                rigid.anchors.iter().enumerate().any( |(i, a)| rigid.anchors[ i + 1.. ].contains( a ) ),
+        }
+    }
+
+    // Rewires the constraint from one anchor to another. Returns false if the
+    // constraint is degenerate afterwards and should be removed.
+    pub fn replace_anchor( &mut self, from : AnchorId, to : AnchorId ) -> bool {
+        for anc in self.anchors_mut() {
+            if *anc == from {
+                *anc = to;
+            }
+        }
+
+        // a pair that collapsed to one anchor can just go, the rest are still equal
+        if let Constraint::EqualLength( equal_len ) = self {
+            equal_len.pairs.retain( |(a, b)| a != b );
+        }
+
+        // same for a corner of a room that got merged into the next one
+        if let Constraint::Area( area ) = self {
+            area.anchors.dedup();
+            if area.anchors.len() > 1 && area.anchors.first() == area.anchors.last() {
+                area.anchors.pop();
+            }
+        }
+
+        // or a point on a straight run merged into its neighbour
+        if let Constraint::Collinear( collinear ) = self {
+            collinear.anchors.dedup();
+        }
+
+        !self.is_degenerate()
+    }
+
+    // Called when an anchor is removed. Returns false if the constraint
+    // doesn't make sense without it and should be removed as well.
+    pub fn drop_anchor( &mut self, id : AnchorId ) -> bool {
+        match self {
+            Constraint::EqualLength( equal_len ) => {
+                equal_len.pairs.retain( |(a, b)| *a != id && *b != id );
+                equal_len.pairs.len() >= 2
+            }
+            Constraint::Symmetry( symmetry ) => {
+                // the other pairs are still symmetric, as long as the axis is still there
+                symmetry.pairs.retain( |(a, b)| *a != id && *b != id );
+                !symmetry.pairs.is_empty() && !matches!( symmetry.axis, SymmetryAxis::Anchors( a, b ) if a == id || b == id )
+            }
+            Constraint::Area( area ) => {
+                // the room just loses a corner
+                area.anchors.retain( |a| *a != id );
+                area.anchors.len() >= 3
+            }
+            Constraint::Collinear( collinear ) => {
+                // the rest of the run is still straight
+                collinear.anchors.retain( |a| *a != id );
+                collinear.anchors.len() >= 3
+            }
+            Constraint::Rigid( rigid ) => {
+                // the rest of the group keeps its shape
+                if let Some( ndx ) = rigid.anchors.iter().position( |a| *a == id ) {
+                    rigid.anchors.remove( ndx );
+                    rigid.shape.remove( ndx );
+                }
+                rigid.anchors.len() >= 2
+            }
+            _ => !self.anchors().contains( &id ),
+        }
+    }
+
+    // What the ndx'th of the constraint's residuals measures
+    pub fn residual_kind( &self, ndx : usize ) -> ResidualKind {
+        match self {
+            Constraint::FixedLength( _ ) => ResidualKind::Length,
+            Constraint::Parallel( _ ) => ResidualKind::Parallel,
+            // the parallel residual, then the offset
+            Constraint::ParallelOffset( _ ) if ndx == 0 => ResidualKind::Parallel,
+            Constraint::ParallelOffset( _ ) => ResidualKind::Length,
+            Constraint::Perpendicular( _ ) => ResidualKind::Angle,
+            Constraint::Horizontal( _ ) | Constraint::Vertical( _ ) => ResidualKind::Length,
+            Constraint::EqualLength( _ ) => ResidualKind::Length,
+            Constraint::PointOnSegment( _ ) => ResidualKind::Length,
+            Constraint::Angle( _ ) => ResidualKind::Angle,
+            Constraint::Measurement( _ ) => ResidualKind::Length,
+            Constraint::Symmetry( _ ) => ResidualKind::Length,
+            Constraint::Area( _ ) => ResidualKind::Area,
+            Constraint::LengthRange( _ ) => ResidualKind::Length,
+            Constraint::AngleRange( _ ) => ResidualKind::Angle,
+            Constraint::Collinear( _ ) => ResidualKind::Length,
+            Constraint::Rigid( _ ) => ResidualKind::Length,
+        }
+    }
+
+    // True for a range that's satisfied, so it isn't doing anything at the moment
+    pub fn is_inactive( &self, anchors : &SlotMap<AnchorId, AnchorPoint> ) -> bool {
+        match self {
+            Constraint::LengthRange( len_range ) => len_range.violated( anchors ).is_none(),
+            Constraint::AngleRange( ang_range ) => ang_range.violated( anchors ).is_none(),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use super::{ cluster, lm, Constraint, ConstraintSystem, MeasurementResidual, Real, SurveyReport };
+
+// Survey mode, for drawing up an existing building from tape measurements.
+// Every wall length and diagonal that was measured goes in as a measurement
+// constraint, and the plan is fitted to all of them at once as a weighted least
+// squares problem, each measurement weighted by one over its sigma. The tape is
+// never exactly right, so the measurements won't agree, and the residuals show
+// which ones disagree with the rest the most.
+//
+// Plain least squares spreads one misread measurement over all the others, so
+// the fit is reweighted a few times (Huber), trusting measurements less the
+// further they are off. The misread one ends up carrying its own error.
+// A measurement that nothing else checks always fits exactly, however wrong it is.
+
+// Measurements within this many sigmas get their full weight
+const HUBER_K : Real = 1.5;
+const REWEIGHT_ROUNDS : usize = 8;
+
+impl ConstraintSystem
+{
+    // Fits the plan to the measurements (and whatever other constraints there are)
+    // with least squares, whatever backend is set. The fit counts as solved, so
+    // eval_system leaves it alone until something changes. Solving again from
+    // there goes back to the measurements' own sigmas.
+    pub fn fit_survey( &mut self ) -> SurveyReport {
+        let mut fit = self.clone();
+        let mut iterations = lm::solve( &mut fit );
+
+        for _ in 0..REWEIGHT_ROUNDS {
+            // loosen the measurements that are off by more than HUBER_K sigmas,
+            // the weight goes on the squared residual so the sigma scales by the root
+            let mut changed = false;
+            for (id, cons) in fit.constraints.iter_mut() {
+                let (Constraint::Measurement( measurement ), Constraint::Measurement( orig )) = (cons, &self.constraints[ id ]) else {
+                    continue;
+                };
+                let normalized = (measurement.length( &fit.anchors ) - orig.measured).abs() * orig.weight();
+                let sigma = orig.sigma * Real::max( normalized / HUBER_K, 1.0 ).sqrt();
+                changed |= (sigma - measurement.sigma).abs() > measurement.sigma * 0.01;
+                measurement.sigma = sigma;
+            }
+
+            if !changed {
+                break;
+            }
+            iterations += lm::solve( &mut fit );
+        }
+
+        for (id, anc) in self.anchors.iter_mut() {
+            // never let a NaN out of the fit, same as eval_system
+            let p = fit.anchors[ id ].p;
+            if p.is_finite() {
+                anc.p = p;
+            }
+        }
+
+        // a plain solve would pull the misread measurements back into line, so the
+        // clusters that fitted are settled. One whose hard constraints the fit
+        // couldn't meet is left for eval_system to carry on with.
+        self.settled.check_settings( &self.settings );
+        self.revision += 1;
+        self.dirty = false;
+        for cluster in cluster::find_clusters( self ).iter() {
+            let (sub, _) = self.extract_cluster( cluster );
+            if sub.residual_report().converged {
+                self.settled.settle( &self.anchors, &self.constraints, cluster );
+            } else {
+                self.settled.unsettle( cluster );
+                self.dirty = true;
+            }
+        }
+
+        let mut report = self.survey_report();
+        report.iterations = iterations;
+        report
+    }
+
+    // How far each measurement is from the current positions, without fitting
+    pub fn survey_report( &self ) -> SurveyReport {
+        let mut report = SurveyReport::default();
+        let mut sum_sq = 0.0;
+
+        for (id, cons) in self.constraints.iter() {
+            let Constraint::Measurement( measurement ) = cons else {
+                continue;
+            };
+
+            let fitted = measurement.length( &self.anchors );
+            let residual = fitted - measurement.measured;
+            let normalized = residual * measurement.weight();
+            sum_sq += normalized * normalized;
+
+            report.measurements.push( MeasurementResidual {
+                constraint : id,
+                measured : measurement.measured,
+                fitted,
+                residual,
+                normalized,
+            });
+        }
+
+        if !report.measurements.is_empty() {
+            report.rms_normalized = (sum_sq / report.measurements.len() as Real).sqrt();
+        }
+        report.measurements.sort_by( |a, b| b.normalized.abs().total_cmp( &a.normalized.abs() ) );
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn misread_measurement_has_the_largest_residual() {
+        // A 4 x 3 room taped along the walls, across the diagonals and out to a
+        // post in the middle, with one wall misread by 0.5
+        let mut csys = ConstraintSystem::new();
+        let a = csys.add_anchor( Vec2::new( 0.0, 0.0 ) );
+        let b = csys.add_anchor( Vec2::new( 4.1, 0.2 ) );
+        let c = csys.add_anchor( Vec2::new( 3.9, 3.1 ) );
+        let d = csys.add_anchor( Vec2::new( -0.1, 2.9 ) );
+        let e = csys.add_anchor( Vec2::new( 2.1, 1.4 ) );
+        csys.anchors[ a ].pin = PinMode::PinXY;
+
+        let misread = csys.add_constraint_measurement( a, b, Some( 4.5 ), 0.01 );
+        csys.add_constraint_measurement( b, c, Some( 3.0 ), 0.01 );
+        csys.add_constraint_measurement( c, d, Some( 4.0 ), 0.01 );
+        csys.add_constraint_measurement( d, a, Some( 3.0 ), 0.01 );
+        csys.add_constraint_measurement( a, c, Some( 5.0 ), 0.01 );
+        csys.add_constraint_measurement( b, d, Some( 5.0 ), 0.01 );
+        for corner in [ a, b, c, d ] {
+            csys.add_constraint_measurement( corner, e, Some( 2.5 ), 0.01 );
+        }
+
+        let report = csys.fit_survey();
+        let worst = report.worst().unwrap();
+        assert_eq!( worst.constraint, misread );
+        assert!( worst.residual.abs() > 0.25, "misread by 0.5, fitted residual {}", worst.residual );
+
+        // and solving again doesn't undo the fit
+        let fitted = csys.anchors[ b ].p;
+        assert!( !csys.needs_solve() );
+        csys.eval_system();
+        assert_eq!( csys.anchors[ b ].p, fitted );
+    }
+
+    #[test]
+    fn fit_that_misses_a_hard_constraint_isnt_settled() {
+        // the measurements say 4, a hard fixed length says 5, and the fit can
+        // only go so far against a hard constraint with a weight of a thousand
+        let mut csys = ConstraintSystem::new();
+        let a = csys.add_anchor( Vec2::new( 0.0, 0.0 ) );
+        let b = csys.add_anchor( Vec2::new( 4.0, 0.0 ) );
+        csys.anchors[ a ].pin = PinMode::PinXY;
+        csys.add_constraint_measurement( a, b, Some( 4.0 ), 1e-3 );
+        csys.add_constraint_fixed_len( a, b, Some( 5.0 ) );
+
+        csys.fit_survey();
+        assert!( !csys.residual_report().converged );
+        assert!( csys.needs_solve() );
+        assert!( csys.anchors[ b ].p.is_finite() );
+    }
+}
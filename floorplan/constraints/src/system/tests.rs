@@ -0,0 +1,994 @@
+use super::*;
+
+// A slightly skewed square, so nothing starts out parallel, perpendicular or
+// on top of anything else
+fn skewed_square( csys : &mut ConstraintSystem ) -> [AnchorId; 4] {
+    [
+        csys.add_anchor( Vec2::new( 0.0, 0.0 ) ),
+        csys.add_anchor( Vec2::new( 100.0, 10.0 ) ),
+        csys.add_anchor( Vec2::new( 95.0, 105.0 ) ),
+        csys.add_anchor( Vec2::new( -8.0, 90.0 ) ),
+    ]
+}
+
+// Moves every anchor a different little way, so constraints that were added
+// at the current positions aren't satisfied any more
+fn nudge( csys : &mut ConstraintSystem ) {
+    for (ndx, anc) in csys.anchors.values_mut().enumerate() {
+        let k = ndx as Real + 1.0;
+        anc.p += Vec2::new( (k * 2.3).sin() * 6.0, (k * 1.7).cos() * 6.0 );
+    }
+}
+
+fn length( csys : &ConstraintSystem, a : AnchorId, b : AnchorId ) -> Real {
+    csys.anchors[ a ].p.distance( csys.anchors[ b ].p )
+}
+
+// Solves frame after frame like the app does, until nothing's left to solve
+fn settle( csys : &mut ConstraintSystem ) -> SolveReport {
+    let mut report = csys.eval_system();
+    for _ in 0..1000 {
+        if !csys.needs_solve() {
+            break;
+        }
+        report = csys.eval_system();
+    }
+    report
+}
+
+// Every residual's gradient against central differences, moving each anchor
+// the constraint uses a little in x and in y
+fn check_gradients( csys : &ConstraintSystem, id : ConstraintId ) {
+    let cons = &csys.constraints[ id ];
+    let mut residuals = Vec::new();
+    cons.residuals( &csys.anchors, &mut residuals );
+
+    let h : Real = 1e-2;
+    let mut anchors = csys.anchors.clone();
+    for anc in cons.anchors() {
+        for axis in 0..2 {
+            let mut delta = Vec2::ZERO;
+            delta[ axis ] = h;
+
+            let p = anchors[ anc ].p;
+            let mut plus = Vec::new();
+            anchors[ anc ].p = p + delta;
+            cons.residuals( &anchors, &mut plus );
+            let mut minus = Vec::new();
+            anchors[ anc ].p = p - delta;
+            cons.residuals( &anchors, &mut minus );
+            anchors[ anc ].p = p;
+
+            for (ndx, r) in residuals.iter().enumerate() {
+                let numeric = (plus[ ndx ].value - minus[ ndx ].value) / (2.0 * h);
+                let analytic : Real = r.grad.iter().filter( |(a, _)| *a == anc ).map( |(_, g)| g[ axis ] ).sum();
+                assert!( (numeric - analytic).abs() <= 1e-2 * numeric.abs().max( 1.0 ),
+                         "residual {} axis {}: analytic {} numeric {}", ndx, axis, analytic, numeric );
+            }
+        }
+    }
+}
+
+// ====== [ Least squares ]==============================
+
+#[test]
+fn length_parallel_and_angle_gradients() {
+    let mut csys = ConstraintSystem::new();
+    let [a, b, c, d] = skewed_square( &mut csys );
+    let ids = [
+        csys.add_constraint_fixed_len( a, b, Some( 120.0 ) ),
+        csys.add_constraint_parallel( a, b, d, c ),
+        csys.add_constraint_angle( a, b, c, Some( 1.2 ) ),
+    ];
+    nudge( &mut csys );
+
+    for id in ids {
+        check_gradients( &csys, id );
+    }
+}
+
+#[test]
+fn least_squares_solves_a_dimensioned_corner() {
+    let mut csys = ConstraintSystem::new();
+    csys.settings.backend = SolverBackend::LevenbergMarquardt;
+    let [a, b, c, d] = skewed_square( &mut csys );
+
+    csys.add_constraint_fixed_len( a, b, Some( 200.0 ) );
+    csys.add_constraint_fixed_len( b, c, Some( 100.0 ) );
+    csys.add_constraint_angle( a, b, c, Some( consts::PI * 1.5 ) ); // a right angle, the way the square goes round
+    csys.add_constraint_parallel( a, b, d, c );
+
+    let report = csys.eval_system();
+    assert!( report.converged, "max residual {}", report.max_residual );
+
+    let tolerance = csys.settings.tolerance;
+    assert!( (length( &csys, a, b ) - 200.0).abs() < tolerance );
+    assert!( (length( &csys, b, c ) - 100.0).abs() < tolerance );
+}
+
+// ====== [ Solve report ]==============================
+
+#[test]
+fn report_lists_residuals_and_convergence() {
+    let mut csys = ConstraintSystem::new();
+    csys.settings.backend = SolverBackend::LevenbergMarquardt;
+    let [a, b, _, _] = skewed_square( &mut csys );
+    let short = csys.add_constraint_fixed_len( a, b, Some( 100.0 ) );
+
+    let report = csys.eval_system();
+    assert!( report.converged );
+    assert!( report.iterations > 0 );
+    assert!( report.max_residual <= csys.settings.tolerance );
+    assert_eq!( report.residuals.len(), 1 );
+    assert_eq!( report.residuals[0].constraint, short );
+    assert_eq!( report.residuals[0].kind, ResidualKind::Length );
+
+    // a second length that disagrees can't be met, and it says so
+    let long = csys.add_constraint_fixed_len( a, b, Some( 150.0 ) );
+    let report = csys.eval_system();
+    assert!( !report.converged );
+    assert_eq!( report.residuals.len(), 2 );
+    assert!( report.max_residual > 20.0 );
+
+    // the error is signed, too short on one and too long on the other
+    let error = |id| report.residuals.iter().find( |r| r.constraint == id ).unwrap().error;
+    assert!( error( short ) > 0.0 && error( long ) < 0.0 );
+}
+
+// ====== [ Deleting ]==============================
+
+#[test]
+fn removing_an_anchor_drops_its_constraints() {
+    let mut csys = ConstraintSystem::new();
+    let [a, b, c, d] = skewed_square( &mut csys );
+    let ab = csys.add_constraint_fixed_len( a, b, None );
+    let cd = csys.add_constraint_fixed_len( c, d, None );
+    let equal = csys.add_constraint_equal_len( &[ (a, b), (c, d), (b, c) ] );
+
+    assert!( csys.remove_anchor( a ).is_some() );
+    assert!( !csys.anchors.contains_key( a ) );
+    assert!( !csys.constraints.contains_key( ab ) );
+
+    // the rest keep their handles, and the equal lengths lose the pair that used a
+    assert_eq!( csys.anchors[ d ].p, Vec2::new( -8.0, 90.0 ) );
+    assert!( csys.constraints.contains_key( cd ) );
+    let Constraint::EqualLength( equal ) = &csys.constraints[ equal ] else {
+        panic!( "not equal lengths any more" );
+    };
+    assert_eq!( equal.pairs, vec![ (c, d), (b, c) ] );
+
+    // removing it again does nothing
+    assert!( csys.remove_anchor( a ).is_none() );
+    assert!( csys.remove_constraint( ab ).is_none() );
+    assert!( csys.remove_constraint( cd ).is_some() );
+}
+
+// ====== [ Perpendicular, horizontal and vertical ]==============================
+
+#[test]
+fn perpendicular_gradients_and_solve() {
+    let mut csys = ConstraintSystem::new();
+    csys.settings.backend = SolverBackend::LevenbergMarquardt;
+    let [a, b, c, d] = skewed_square( &mut csys );
+    let perp = csys.add_constraint_perpendicular( a, b, c, d );
+    nudge( &mut csys );
+    check_gradients( &csys, perp );
+
+    assert!( csys.eval_system().converged );
+    let ab = csys.anchors[ b ].p - csys.anchors[ a ].p;
+    let cd = csys.anchors[ d ].p - csys.anchors[ c ].p;
+    assert!( ab.normalize().dot( cd.normalize() ).abs() < 1e-3 );
+}
+
+#[test]
+fn horizontal_and_vertical_gradients_and_solve() {
+    let mut csys = ConstraintSystem::new();
+    csys.settings.backend = SolverBackend::LevenbergMarquardt;
+    let [a, b, c, _] = skewed_square( &mut csys );
+    let ids = [ csys.add_constraint_horizontal( a, b ), csys.add_constraint_vertical( b, c ) ];
+    nudge( &mut csys );
+    for id in ids {
+        check_gradients( &csys, id );
+    }
+
+    assert!( csys.eval_system().converged );
+    let tolerance = csys.settings.tolerance;
+    assert!( (csys.anchors[ a ].p.y - csys.anchors[ b ].p.y).abs() < tolerance );
+    assert!( (csys.anchors[ b ].p.x - csys.anchors[ c ].p.x).abs() < tolerance );
+}
+
+// ====== [ Equal length ]==============================
+
+#[test]
+fn equal_length_gradients_and_solve() {
+    let mut csys = ConstraintSystem::new();
+    csys.settings.backend = SolverBackend::LevenbergMarquardt;
+    let [a, b, c, d] = skewed_square( &mut csys );
+    let equal = csys.add_constraint_equal_len( &[ (a, b), (b, c), (c, d) ] );
+    nudge( &mut csys );
+    check_gradients( &csys, equal );
+
+    assert!( csys.eval_system().converged );
+    let ab = length( &csys, a, b );
+    for (p, q) in [ (b, c), (c, d) ] {
+        assert!( (length( &csys, p, q ) - ab).abs() < 1e-2 );
+    }
+}
+
+// ====== [ Point on segment ]==============================
+
+#[test]
+fn point_on_segment_gradients_and_solve() {
+    let mut csys = ConstraintSystem::new();
+    csys.settings.backend = SolverBackend::LevenbergMarquardt;
+    let a = csys.add_anchor( Vec2::new( 0.0, 0.0 ) );
+    let b = csys.add_anchor( Vec2::new( 100.0, 5.0 ) );
+    let p = csys.add_anchor( Vec2::new( 30.0, 20.0 ) );
+    let q = csys.add_anchor( Vec2::new( 60.0, -15.0 ) );
+    let free = csys.add_constraint_point_on_segment( p, a, b, None );
+    let ratio = csys.add_constraint_point_on_segment( q, a, b, Some( 0.75 ) );
+    check_gradients( &csys, free );
+    check_gradients( &csys, ratio );
+
+    assert!( csys.eval_system().converged );
+    let pa = csys.anchors[ a ].p;
+    let pb = csys.anchors[ b ].p;
+    let ab = (pb - pa).normalize();
+    let pp = csys.anchors[ p ].p;
+    assert!( (pp - pa).perp_dot( ab ).abs() < 1e-2 );
+    assert!( csys.anchors[ q ].p.distance( pa.lerp( pb, 0.75 ) ) < 1e-2 );
+}
+
+#[test]
+fn point_past_the_end_is_pulled_onto_the_segment() {
+    let mut csys = ConstraintSystem::new();
+    let a = csys.add_anchor( Vec2::new( 0.0, 0.0 ) );
+    let b = csys.add_anchor( Vec2::new( 100.0, 0.0 ) );
+    let p = csys.add_anchor( Vec2::new( 140.0, 10.0 ) );
+    csys.anchors[ a ].pin = PinMode::PinXY;
+    csys.anchors[ b ].pin = PinMode::PinXY;
+    csys.add_constraint_point_on_segment( p, a, b, None );
+
+    assert!( settle( &mut csys ).converged );
+    // the free ratio is clamped, so P lands on the end, not on the line past it
+    assert!( csys.anchors[ p ].p.distance( Vec2::new( 100.0, 0.0 ) ) < 0.1 );
+}
+
+// ====== [ Collinear ]==============================
+
+#[test]
+fn collinear_gradients_and_solve() {
+    for backend in [ SolverBackend::Relaxation, SolverBackend::LevenbergMarquardt ] {
+        let mut csys = ConstraintSystem::new();
+        csys.settings.backend = backend;
+        let [a, b, c, d] = skewed_square( &mut csys );
+        let id = csys.add_constraint_collinear( &[ a, b, c, d ] ).unwrap();
+        check_gradients( &csys, id );
+
+        let report = settle( &mut csys );
+        assert!( report.converged, "max residual {}", report.max_residual );
+        let (pa, dir) = (csys.anchors[ a ].p, (csys.anchors[ d ].p - csys.anchors[ a ].p).normalize());
+        for anc in [ b, c ] {
+            assert!( dir.perp_dot( csys.anchors[ anc ].p - pa ).abs() < 1e-2 );
+        }
+    }
+}
+
+#[test]
+fn collinear_takes_at_least_three_anchors() {
+    let mut csys = ConstraintSystem::new();
+    let [a, b, c, _] = skewed_square( &mut csys );
+    assert!( csys.add_constraint_collinear( &[] ).is_none() );
+    assert!( csys.add_constraint_collinear( &[ a ] ).is_none() );
+    assert!( csys.add_constraint_collinear( &[ a, b ] ).is_none() );
+    assert!( csys.constraints.is_empty() );
+
+    // and removing anchors down to two drops it, rather than leaving a run of one
+    csys.add_constraint_collinear( &[ a, b, c ] ).unwrap();
+    csys.remove_anchor( b );
+    assert!( csys.constraints.is_empty() );
+}
+
+// ====== [ Rigid ]==============================
+
+#[test]
+fn rigid_gradients() {
+    let mut csys = ConstraintSystem::new();
+    let [a, b, c, d] = skewed_square( &mut csys );
+    let id = csys.add_constraint_rigid( &[ a, b, c, d ] );
+    nudge( &mut csys );
+    check_gradients( &csys, id );
+}
+
+#[test]
+fn rigid_group_moves_as_a_unit() {
+    for backend in [ SolverBackend::Relaxation, SolverBackend::LevenbergMarquardt ] {
+        // lock the square, then drag one corner off with a hard length to a pinned anchor
+        let mut csys = ConstraintSystem::new();
+        csys.settings.backend = backend;
+        // in f32, relaxation stalls a little short of the default tolerance here
+        csys.settings.tolerance = 1e-2;
+        let [a, b, c, d] = skewed_square( &mut csys );
+        let before : Vec<Vec2> = [a, b, c, d].iter().map( |id| csys.anchors[ *id ].p ).collect();
+        csys.add_constraint_rigid( &[ a, b, c, d ] );
+        let post = csys.add_anchor( Vec2::new( -200.0, -150.0 ) );
+        csys.anchors[ post ].pin = PinMode::PinXY;
+        csys.add_constraint_fixed_len( post, a, Some( 100.0 ) );
+
+        let report = settle( &mut csys );
+        assert!( report.converged, "max residual {}", report.max_residual );
+        assert!( (length( &csys, post, a ) - 100.0).abs() < 1e-1 );
+
+        // the whole group went along, every distance in it is the same as before
+        let after : Vec<Vec2> = [a, b, c, d].iter().map( |id| csys.anchors[ *id ].p ).collect();
+        assert!( after[ 0 ].distance( before[ 0 ] ) > 50.0 );
+        for i in 0..4 {
+            for j in (i + 1)..4 {
+                let change = after[ i ].distance( after[ j ] ) - before[ i ].distance( before[ j ] );
+                assert!( change.abs() < 1e-1, "{} to {} changed by {}", i, j, change );
+            }
+        }
+    }
+}
+
+// ====== [ Merging ]==============================
+
+#[test]
+fn merging_rewires_constraints_and_keeps_pins() {
+    let mut csys = ConstraintSystem::new();
+    let [a, b, c, d] = skewed_square( &mut csys );
+    csys.anchors[ d ].pin = PinMode::PinX;
+    csys.anchors[ c ].pin = PinMode::PinY;
+    let bc = csys.add_constraint_fixed_len( b, c, None );
+    let cd = csys.add_constraint_fixed_len( c, d, None );
+    let ab = csys.add_constraint_horizontal( a, b );
+    csys.eval_system();
+    let revision = csys.revision;
+
+    csys.merge_anchors( c, d );
+    assert!( !csys.anchors.contains_key( d ) );
+    assert!( csys.anchors[ c ].pin == PinMode::PinXY );
+
+    // C to D is now C to C, so it goes, and B to C is untouched
+    assert!( !csys.constraints.contains_key( cd ) );
+    assert!( csys.constraints.contains_key( bc ) );
+    assert!( csys.constraints.contains_key( ab ) );
+    assert!( csys.revision > revision );
+    assert!( csys.needs_solve() );
+
+    // merging into itself or a missing anchor does nothing
+    let revision = csys.revision;
+    csys.merge_anchors( c, c );
+    csys.merge_anchors( c, d );
+    assert_eq!( csys.revision, revision );
+    assert_eq!( csys.anchors.len(), 3 );
+}
+
+#[test]
+fn merging_rewires_the_other_end() {
+    let mut csys = ConstraintSystem::new();
+    let [a, b, c, _] = skewed_square( &mut csys );
+    let ab = csys.add_constraint_fixed_len( a, b, Some( 50.0 ) );
+
+    csys.merge_anchors( c, b );
+    let Constraint::FixedLength( fixed_len ) = &csys.constraints[ ab ] else {
+        panic!( "the fixed length changed kind" );
+    };
+    assert_eq!( (fixed_len.anc_a, fixed_len.anc_b), (a, c) );
+}
+
+// ====== [ Parallel offset ]==============================
+
+#[test]
+fn parallel_offset_gradients_and_solve() {
+    for backend in [ SolverBackend::Relaxation, SolverBackend::LevenbergMarquardt ] {
+        let mut csys = ConstraintSystem::new();
+        csys.settings.backend = backend;
+        let [a, b, c, d] = skewed_square( &mut csys );
+        csys.anchors[ a ].pin = PinMode::PinXY;
+        csys.anchors[ b ].pin = PinMode::PinXY;
+
+        // DC to the left of AB, 80 away
+        let id = csys.add_constraint_parallel_offset( a, b, d, c, Some( 80.0 ) );
+        nudge( &mut csys );
+        check_gradients( &csys, id );
+
+        let report = settle( &mut csys );
+        assert!( report.converged, "max residual {}", report.max_residual );
+        let [pa, pb, pc, pd] = [a, b, c, d].map( |id| csys.anchors[ id ].p );
+        assert!( (ParallelOffsetConstraint::offset( pa, pb, pc, pd ) - 80.0).abs() < 1e-2 );
+        assert!( (pb - pa).normalize().perp_dot( (pc - pd).normalize() ).abs() < 1e-3 );
+    }
+}
+
+#[test]
+fn parallel_offset_reports_an_angle_and_a_distance() {
+    let mut csys = ConstraintSystem::new();
+    let [a, b, c, d] = skewed_square( &mut csys );
+    let id = csys.add_constraint_parallel_offset( a, b, d, c, Some( 80.0 ) );
+
+    let report = csys.residual_report();
+    let kinds : Vec<ResidualKind> = report.residuals.iter().filter( |r| r.constraint == id ).map( |r| r.kind ).collect();
+    assert_eq!( kinds, vec![ ResidualKind::Parallel, ResidualKind::Length ] );
+}
+
+// ====== [ Signed angles ]==============================
+
+#[test]
+fn reflex_angle_is_reached_not_mirrored() {
+    for backend in [ SolverBackend::Relaxation, SolverBackend::LevenbergMarquardt ] {
+        let mut csys = ConstraintSystem::new();
+        csys.settings.backend = backend;
+        let a = csys.add_anchor( Vec2::new( 100.0, 0.0 ) );
+        let b = csys.add_anchor( Vec2::new( 0.0, 0.0 ) );
+        let c = csys.add_anchor( Vec2::new( 0.0, 100.0 ) );
+        csys.anchors[ a ].pin = PinMode::PinXY;
+        csys.anchors[ b ].pin = PinMode::PinXY;
+
+        // 250 degrees is 110 the other way round, which an unsigned angle couldn't tell apart
+        let target = (250.0 as Real).to_radians();
+        let id = csys.add_constraint_angle( a, b, c, Some( target ) );
+        check_gradients( &csys, id );
+
+        // the mirror image isn't a solution
+        assert!( csys.residual_report().max_residual > 0.1 );
+
+        assert!( settle( &mut csys ).converged );
+        let ang = AngleConstraint::angle( csys.anchors[ a ].p, csys.anchors[ b ].p, csys.anchors[ c ].p );
+        assert!( wrap_angle( ang - target ).abs() < 1e-2, "angle ended up at {}", ang.to_degrees() );
+    }
+}
+
+#[test]
+fn negative_target_angles_wrap_around() {
+    let mut csys = ConstraintSystem::new();
+    let [a, b, c, _] = skewed_square( &mut csys );
+    let id = csys.add_constraint_angle( a, b, c, Some( -consts::FRAC_PI_2 ) );
+    let Constraint::Angle( angle ) = &csys.constraints[ id ] else {
+        panic!( "not an angle" );
+    };
+    assert!( (angle.target_angle - consts::PI * 1.5).abs() < 1e-4 );
+}
+
+// ====== [ Symmetry ]==============================
+
+#[test]
+fn symmetry_gradients() {
+    let mut csys = ConstraintSystem::new();
+    let [a, b, c, d] = skewed_square( &mut csys );
+    let ids = [
+        csys.add_constraint_symmetry( SymmetryAxis::Anchors( a, c ), &[ (b, d) ] ),
+        csys.add_constraint_symmetry( SymmetryAxis::Fixed { point : Vec2::new( 50.0, 0.0 ), dir : Vec2::Y }, &[ (a, b), (d, c) ] ),
+    ];
+    nudge( &mut csys );
+
+    for id in ids {
+        check_gradients( &csys, id );
+    }
+}
+
+#[test]
+fn pairs_end_up_mirrored_across_the_axis() {
+    for backend in [ SolverBackend::Relaxation, SolverBackend::LevenbergMarquardt ] {
+        // mirrored across the diagonal AC, which can move too
+        let mut csys = ConstraintSystem::new();
+        csys.settings.backend = backend;
+        let [a, b, c, d] = skewed_square( &mut csys );
+        csys.add_constraint_symmetry( SymmetryAxis::Anchors( a, c ), &[ (b, d) ] );
+
+        assert!( settle( &mut csys ).converged );
+        let (origin, dir) = (csys.anchors[ a ].p, (csys.anchors[ c ].p - csys.anchors[ a ].p).normalize());
+        let pb = csys.anchors[ b ].p - origin;
+        let pd = csys.anchors[ d ].p - origin;
+        assert!( (pb.dot( dir ) - pd.dot( dir )).abs() < 1e-2 );
+        assert!( (pb.perp_dot( dir ) + pd.perp_dot( dir )).abs() < 1e-2 );
+
+        // and across a fixed vertical line at x = 50
+        let mut csys = ConstraintSystem::new();
+        csys.settings.backend = backend;
+        let [a, b, _, _] = skewed_square( &mut csys );
+        csys.add_constraint_symmetry( SymmetryAxis::Fixed { point : Vec2::new( 50.0, 0.0 ), dir : Vec2::Y }, &[ (a, b) ] );
+
+        assert!( settle( &mut csys ).converged );
+        let (pa, pb) = (csys.anchors[ a ].p, csys.anchors[ b ].p);
+        assert!( (pa.x + pb.x - 100.0).abs() < 1e-2 );
+        assert!( (pa.y - pb.y).abs() < 1e-2 );
+    }
+}
+
+// ====== [ Area ]==============================
+
+#[test]
+fn area_gradients_and_solve() {
+    for backend in [ SolverBackend::Relaxation, SolverBackend::LevenbergMarquardt ] {
+        let mut csys = ConstraintSystem::new();
+        csys.settings.backend = backend;
+        let [a, b, c, d] = skewed_square( &mut csys );
+        let id = csys.add_constraint_area( &[ a, b, c, d ], Some( 12000.0 ) );
+        check_gradients( &csys, id );
+
+        let report = settle( &mut csys );
+        assert!( report.converged, "max residual {}", report.max_residual );
+        assert_eq!( report.residuals[0].kind, ResidualKind::Area );
+        let Constraint::Area( area ) = &csys.constraints[ id ] else {
+            unreachable!();
+        };
+        assert!( (area.area( &csys.anchors ) - 12000.0).abs() < 1.0 );
+    }
+}
+
+#[test]
+fn area_defaults_to_the_current_one_and_is_signed() {
+    let mut csys = ConstraintSystem::new();
+    let [a, b, c, d] = skewed_square( &mut csys );
+    let id = csys.add_constraint_area( &[ a, b, c, d ], None );
+    let Constraint::Area( area ) = &csys.constraints[ id ] else {
+        unreachable!();
+    };
+    assert!( area.target_area > 0.0 );
+    assert!( (area.area( &csys.anchors ) - area.target_area).abs() < 1e-3 );
+    assert!( csys.residual_report().converged );
+
+    // the other way round is clockwise
+    let id = csys.add_constraint_area( &[ d, c, b, a ], None );
+    let Constraint::Area( area ) = &csys.constraints[ id ] else {
+        unreachable!();
+    };
+    assert!( area.target_area < 0.0 );
+}
+
+// ====== [ Ranges ]==============================
+
+#[test]
+fn range_gradients() {
+    let mut csys = ConstraintSystem::new();
+    let [a, b, c, d] = skewed_square( &mut csys );
+    let ids = [
+        csys.add_constraint_length_range( a, c, Some( 160.0 ), None ),
+        csys.add_constraint_length_range( b, d, None, Some( 100.0 ) ),
+        csys.add_constraint_angle_range( d, a, b, 0.2, 0.6 ),
+        csys.add_constraint_angle_range( b, c, d, -0.3, 0.3 ),
+    ];
+    nudge( &mut csys );
+
+    for id in ids {
+        check_gradients( &csys, id );
+    }
+}
+
+#[test]
+fn length_range_only_pushes_when_outside() {
+    let mut csys = ConstraintSystem::new();
+    csys.settings.backend = SolverBackend::LevenbergMarquardt;
+    let [a, b, _, _] = skewed_square( &mut csys );
+    csys.anchors[ a ].pin = PinMode::PinXY;
+
+    // already inside, nothing moves
+    let id = csys.add_constraint_length_range( a, b, Some( 50.0 ), Some( 150.0 ) );
+    let before = csys.anchors[ b ].p;
+    assert!( settle( &mut csys ).converged );
+    assert_eq!( csys.anchors[ b ].p, before );
+
+    // too long, pulled in to the max
+    let Constraint::LengthRange( range ) = &mut csys.constraints[ id ] else {
+        unreachable!();
+    };
+    range.max = Some( 80.0 );
+    csys.mark_changed();
+    assert!( settle( &mut csys ).converged );
+    assert!( (length( &csys, a, b ) - 80.0).abs() < 1e-2 );
+}
+
+#[test]
+fn angle_range_wraps_past_zero() {
+    let mut csys = ConstraintSystem::new();
+    let [a, b, c, _] = skewed_square( &mut csys );
+
+    // -20 to 20 degrees, starting from about 90
+    let id = csys.add_constraint_angle_range( a, b, c, (-20.0 as Real).to_radians(), (20.0 as Real).to_radians() );
+    let Constraint::AngleRange( range ) = &csys.constraints[ id ] else {
+        unreachable!();
+    };
+    assert!( range.min_angle > range.max_angle );
+    assert!( (range.span() - (40.0 as Real).to_radians()).abs() < 1e-4 );
+
+    csys.settings.backend = SolverBackend::LevenbergMarquardt;
+    assert!( settle( &mut csys ).converged );
+
+    let ang = AngleConstraint::angle( csys.anchors[ a ].p, csys.anchors[ b ].p, csys.anchors[ c ].p );
+    let from_zero = wrap_angle( ang ).to_degrees();
+    assert!( from_zero.abs() <= 20.0 + 1e-2, "angle ended up at {}", ang.to_degrees() );
+}
+
+// ====== [ Degrees of freedom ]==============================
+
+// Four sides and a right angle pin down the shape of a square, but not where it is
+fn square_with_right_angle( csys : &mut ConstraintSystem ) -> [AnchorId; 4] {
+    let [a, b, c, d] = skewed_square( csys );
+    for (p, q) in [ (a, b), (b, c), (c, d), (d, a) ] {
+        csys.add_constraint_fixed_len( p, q, Some( 100.0 ) );
+    }
+    csys.add_constraint_perpendicular( a, b, b, c );
+    settle( csys );
+    [a, b, c, d]
+}
+
+#[test]
+fn dof_of_an_unpinned_square() {
+    let mut csys = ConstraintSystem::new();
+    square_with_right_angle( &mut csys );
+
+    let report = csys.analyze_dof();
+    assert_eq!( report.dof, 0 );
+    assert_eq!( report.rigid_modes, 3 );
+    assert_eq!( report.num_redundant(), 0 );
+    assert_eq!( report.num_conflicting(), 0 );
+}
+
+#[test]
+fn dof_of_a_pinned_square() {
+    let mut csys = ConstraintSystem::new();
+    let [a, b, _, _] = square_with_right_angle( &mut csys );
+
+    // one corner pinned in place still lets the square spin around it
+    csys.anchors[ a ].pin = PinMode::PinXY;
+    let report = csys.analyze_dof();
+    assert_eq!( (report.dof, report.rigid_modes), (0, 1) );
+
+    // and pinning the next corner's y stops it
+    csys.anchors[ b ].p.y = csys.anchors[ a ].p.y;
+    csys.anchors[ b ].pin = PinMode::PinY;
+    settle( &mut csys );
+    let report = csys.analyze_dof();
+    assert_eq!( (report.dof, report.rigid_modes), (0, 0) );
+
+    // without the right angle it can lean over
+    let perp = csys.constraints.iter().find( |(_, cons)| matches!( cons, Constraint::Perpendicular( _ ) ) ).unwrap().0;
+    csys.remove_constraint( perp );
+    let report = csys.analyze_dof();
+    assert_eq!( (report.dof, report.rigid_modes), (1, 0) );
+}
+
+#[test]
+fn dof_of_separate_clusters() {
+    let mut csys = ConstraintSystem::new();
+    let first = square_with_right_angle( &mut csys );
+    let second = square_with_right_angle( &mut csys );
+    for id in second {
+        csys.anchors[ id ].p += Vec2::new( 300.0, 0.0 );
+    }
+
+    // moving both squares together doesn't count, moving one against the other does
+    let report = csys.analyze_dof();
+    assert_eq!( (report.dof, report.rigid_modes), (3, 3) );
+    assert!( first.iter().chain( second.iter() ).all( |id| report.anchor_dof[ *id ] == 2 ) );
+
+    // with the first one pinned down, only the second can move
+    let [a, b, _, _] = first;
+    csys.anchors[ a ].pin = PinMode::PinXY;
+    csys.anchors[ b ].p.y = csys.anchors[ a ].p.y;
+    csys.anchors[ b ].pin = PinMode::PinY;
+    settle( &mut csys );
+    let report = csys.analyze_dof();
+    assert_eq!( (report.dof, report.rigid_modes), (3, 0) );
+    assert!( first.iter().all( |id| report.anchor_dof[ *id ] == 0 ) );
+    assert!( second.iter().all( |id| report.anchor_dof[ *id ] == 2 ) );
+    assert!( first.iter().all( |id| report.anchor_state( *id ) == DofState::Full ) );
+}
+
+#[test]
+fn dof_of_a_big_grid() {
+    // 30 x 30 anchors with every side dimensioned, which has to be quick
+    let mut csys = ConstraintSystem::new();
+    let k = 30;
+    let ids : Vec<AnchorId> = (0..k * k).map( |i| csys.add_anchor( Vec2::new( (i % k) as Real * 50.0, (i / k) as Real * 50.0 ) ) ).collect();
+    for y in 0..k {
+        for x in 0..k {
+            if x + 1 < k {
+                csys.add_constraint_fixed_len( ids[ y * k + x ], ids[ y * k + x + 1 ], None );
+            }
+            if y + 1 < k {
+                csys.add_constraint_fixed_len( ids[ y * k + x ], ids[ (y + 1) * k + x ], None );
+            }
+        }
+    }
+
+    // a grid of bars can still shear lots of ways, moving the whole thing aside
+    let report = csys.analyze_dof();
+    assert_eq!( (report.dof, report.rigid_modes), (2 * k * k - 2 * k * (k - 1) - 3, 3) );
+    assert_eq!( report.num_redundant() + report.num_conflicting(), 0 );
+}
+
+// ====== [ Conflicts ]==============================
+
+#[test]
+fn conflicting_pair_is_found() {
+    let mut csys = ConstraintSystem::new();
+    let [a, b, c, _] = skewed_square( &mut csys );
+
+    csys.add_constraint_vertical( b, c );
+    let first = csys.add_constraint_fixed_len( a, b, Some( 100.0 ) );
+    let second = csys.add_constraint_fixed_len( a, b, Some( 150.0 ) );
+
+    let mut conflicts = csys.find_conflicts();
+    conflicts.sort();
+    let mut expected = vec![ first, second ];
+    expected.sort();
+    assert_eq!( conflicts, expected );
+
+    csys.remove_constraint( second );
+    assert!( csys.find_conflicts().is_empty() );
+}
+
+// ====== [ Priorities ]==============================
+
+#[test]
+fn weak_constraint_yields_to_a_hard_one() {
+    for backend in [ SolverBackend::Relaxation, SolverBackend::LevenbergMarquardt ] {
+        let mut csys = ConstraintSystem::new();
+        csys.settings.backend = backend;
+        let [a, b, _, _] = skewed_square( &mut csys );
+        csys.add_constraint_fixed_len( a, b, Some( 100.0 ) );
+        let weak = csys.add_constraint_fixed_len( a, b, Some( 150.0 ) );
+        *csys.constraints[ weak ].priority_mut() = Priority::Weak;
+        settle( &mut csys );
+
+        // least squares weighs it a million to one, relaxation about a hundred to one
+        let len = length( &csys, a, b );
+        let slack = match backend {
+            SolverBackend::Relaxation => 1.0,
+            SolverBackend::LevenbergMarquardt => 1e-2,
+        };
+        assert!( (len - 100.0).abs() < slack, "{:?} ended up at {}", backend, len );
+        assert!( len > 100.0 );
+    }
+}
+
+#[test]
+fn only_hard_constraints_count_for_converging() {
+    let mut csys = ConstraintSystem::new();
+    csys.settings.backend = SolverBackend::LevenbergMarquardt;
+    let [a, b, _, _] = skewed_square( &mut csys );
+    csys.add_constraint_fixed_len( a, b, Some( 100.0 ) );
+    let weak = csys.add_constraint_fixed_len( a, b, Some( 150.0 ) );
+    *csys.constraints[ weak ].priority_mut() = Priority::Weak;
+
+    // the weak one is way off, but that's what it's there for
+    let report = settle( &mut csys );
+    assert!( report.converged );
+    assert!( report.max_residual > 40.0 );
+}
+
+// ====== [ Clusters ]==============================
+
+#[test]
+fn settled_clusters_are_skipped() {
+    let mut csys = ConstraintSystem::new();
+    csys.settings.backend = SolverBackend::LevenbergMarquardt;
+    let [a, b, c, d] = skewed_square( &mut csys );
+    csys.add_constraint_fixed_len( a, b, Some( 120.0 ) );
+    csys.add_constraint_fixed_len( c, d, Some( 80.0 ) );
+    assert!( settle( &mut csys ).converged );
+    assert!( !csys.needs_solve() );
+
+    // nothing to do, so nothing changes
+    let revision = csys.revision;
+    let report = csys.eval_system();
+    assert_eq!( (report.iterations, csys.revision), (0, revision) );
+
+    // moving C only solves C and D again
+    let before = [ csys.anchors[ a ].p, csys.anchors[ b ].p ];
+    csys.anchors[ c ].p.x += 30.0;
+    csys.mark_changed();
+    assert!( settle( &mut csys ).converged );
+    assert_eq!( [ csys.anchors[ a ].p, csys.anchors[ b ].p ], before );
+    assert!( (length( &csys, c, d ) - 80.0).abs() < 1e-2 );
+
+    // and changing the settings solves everything again
+    csys.settings.tolerance *= 0.5;
+    assert!( csys.needs_solve() );
+}
+
+#[test]
+fn deleting_a_conflicting_constraint_solves_again() {
+    for backend in [ SolverBackend::Relaxation, SolverBackend::LevenbergMarquardt ] {
+        let mut csys = ConstraintSystem::new();
+        csys.settings.backend = backend;
+        let [a, b, _, _] = skewed_square( &mut csys );
+        csys.add_constraint_fixed_len( a, b, Some( 100.0 ) );
+        let wrong = csys.add_constraint_fixed_len( a, b, Some( 150.0 ) );
+
+        // it gets stuck in between, and stops trying until something changes
+        assert!( !settle( &mut csys ).converged );
+        assert!( !csys.needs_solve() );
+
+        csys.remove_constraint( wrong );
+        assert!( settle( &mut csys ).converged, "{:?}", backend );
+        assert!( (length( &csys, a, b ) - 100.0).abs() < 1e-2, "{:?} ended up at {}", backend, length( &csys, a, b ) );
+    }
+}
+
+#[test]
+fn deleting_a_hard_constraint_lets_a_weak_one_through() {
+    let mut csys = ConstraintSystem::new();
+    csys.settings.backend = SolverBackend::LevenbergMarquardt;
+    let [a, b, _, _] = skewed_square( &mut csys );
+    let hard = csys.add_constraint_fixed_len( a, b, Some( 100.0 ) );
+    let weak = csys.add_constraint_fixed_len( a, b, Some( 150.0 ) );
+    *csys.constraints[ weak ].priority_mut() = Priority::Weak;
+
+    // converged, since only the hard one counts for that
+    assert!( settle( &mut csys ).converged );
+    csys.remove_constraint( hard );
+    settle( &mut csys );
+    assert!( (length( &csys, a, b ) - 150.0).abs() < 1e-2 );
+}
+
+#[test]
+fn removing_and_merging_anchors_solves_again() {
+    let mut csys = ConstraintSystem::new();
+    csys.settings.backend = SolverBackend::LevenbergMarquardt;
+    let [a, b, c, d] = skewed_square( &mut csys );
+    csys.add_constraint_fixed_len( a, b, Some( 100.0 ) );
+    csys.add_constraint_equal_len( &[ (a, b), (c, d) ] );
+    let e = csys.add_anchor( Vec2::new( 50.0, 50.0 ) );
+    csys.add_constraint_fixed_len( c, e, Some( 10.0 ) );
+    csys.add_constraint_fixed_len( d, e, Some( 10.0 ) );
+
+    // C and D can't both be 10 from E and 100 apart
+    assert!( !settle( &mut csys ).converged );
+
+    // without E it's just the equal lengths
+    csys.remove_anchor( e );
+    assert!( settle( &mut csys ).converged );
+    assert!( (length( &csys, c, d ) - 100.0).abs() < 1e-2 );
+
+    // F can't be 100 from A and 300 from B, until welding it onto B drops B to F
+    let f = csys.add_anchor( Vec2::new( 0.0, 200.0 ) );
+    csys.add_constraint_fixed_len( a, f, Some( 100.0 ) );
+    csys.add_constraint_fixed_len( b, f, Some( 300.0 ) );
+    assert!( !settle( &mut csys ).converged );
+    csys.merge_anchors( b, f );
+    assert!( settle( &mut csys ).converged );
+    assert!( (length( &csys, a, b ) - 100.0).abs() < 1e-2 );
+}
+
+// ====== [ Revisions ]==============================
+
+#[test]
+fn revision_counts_every_change() {
+    let mut csys = ConstraintSystem::new();
+    let [a, b, _, _] = skewed_square( &mut csys );
+    let mut revision = csys.revision();
+
+    let mut changed = |csys : &ConstraintSystem| {
+        let bumped = csys.revision() > revision;
+        revision = csys.revision();
+        bumped
+    };
+
+    let id = csys.add_constraint_fixed_len( a, b, Some( 50.0 ) );
+    assert!( changed( &csys ) && csys.needs_solve() );
+
+    // solving moves things, and then there's nothing left to do
+    settle( &mut csys );
+    assert!( changed( &csys ) && !csys.needs_solve() );
+    csys.eval_system();
+    assert!( !changed( &csys ) );
+
+    csys.anchors[ a ].pin = PinMode::PinXY;
+    csys.mark_changed();
+    assert!( changed( &csys ) && csys.needs_solve() );
+
+    csys.remove_constraint( id );
+    assert!( changed( &csys ) );
+}
+
+#[test]
+fn copying_an_older_system_keeps_the_revision_going_up() {
+    let mut csys = ConstraintSystem::new();
+    let [a, b, _, _] = skewed_square( &mut csys );
+    settle( &mut csys );
+    let snapshot = csys.clone();
+
+    csys.add_constraint_fixed_len( a, b, Some( 50.0 ) );
+    settle( &mut csys );
+    let revision = csys.revision();
+
+    // like undo, going back to the snapshot is still a change
+    csys.copy_from( snapshot.clone() );
+    assert!( csys.revision() > revision );
+    assert!( csys.revision() > snapshot.revision() );
+    assert!( csys.needs_solve() );
+    assert!( csys.constraints.is_empty() );
+}
+
+// ====== [ Relaxation ]==============================
+
+// How many frames relaxation takes to square up a skewed square
+fn frames_to_square_up( order : UpdateOrder, adaptive : bool ) -> usize {
+    let mut csys = ConstraintSystem::new();
+    csys.settings.order = order;
+    csys.settings.adaptive = adaptive;
+
+    // Jacobi's averaged steps get too small for f32 to take near the default tolerance
+    csys.settings.tolerance = 1e-2;
+    let [a, b, c, d] = skewed_square( &mut csys );
+    for (p, q) in [ (a, b), (b, c), (c, d), (d, a) ] {
+        csys.add_constraint_fixed_len( p, q, Some( 100.0 ) );
+    }
+    csys.add_constraint_perpendicular( a, b, b, c );
+
+    let mut frames = 0;
+    while csys.needs_solve() && frames < 1000 {
+        csys.eval_system();
+        frames += 1;
+    }
+
+    assert!( csys.residual_report().converged, "{:?} adaptive {} didn't converge", order, adaptive );
+    let diagonal = csys.anchors[ a ].p.distance( csys.anchors[ c ].p );
+    assert!( (diagonal - 100.0 * consts::SQRT_2).abs() < 0.1 );
+    frames
+}
+
+#[test]
+fn relaxation_converges_in_either_order() {
+    for order in [ UpdateOrder::GaussSeidel, UpdateOrder::Jacobi ] {
+        let fixed = frames_to_square_up( order, false );
+        let adaptive = frames_to_square_up( order, true );
+
+        // growing the step while it's helping gets there sooner
+        assert!( adaptive <= fixed, "{:?} took {} frames adaptive, {} without", order, adaptive, fixed );
+    }
+
+    // applying each constraint straight away beats averaging them
+    assert!( frames_to_square_up( UpdateOrder::GaussSeidel, false ) <= frames_to_square_up( UpdateOrder::Jacobi, false ) );
+}
+
+// ====== [ Degenerate geometry ]==============================
+
+#[test]
+fn coincident_anchors_stay_finite_and_are_reported() {
+    for backend in [ SolverBackend::Relaxation, SolverBackend::LevenbergMarquardt ] {
+        let mut csys = ConstraintSystem::new();
+        csys.settings.backend = backend;
+        let a = csys.add_anchor( Vec2::new( 10.0, 10.0 ) );
+        let b = csys.add_anchor( Vec2::new( 10.0, 10.0 ) );
+        let c = csys.add_anchor( Vec2::new( 10.0, 10.0 ) );
+        let len = csys.add_constraint_fixed_len( a, b, Some( 50.0 ) );
+        let angle = csys.add_constraint_angle( a, b, c, Some( 1.0 ) );
+        let perp = csys.add_constraint_perpendicular( a, b, b, c );
+
+        let report = csys.residual_report();
+        for id in [ len, angle, perp ] {
+            assert!( report.degenerate.contains( &id ), "{:?}", backend );
+        }
+
+        // solving skips them (least squares) or nudges them apart (relaxation),
+        // rather than filling the plan with NaNs
+        settle( &mut csys );
+        assert!( csys.anchors.values().all( |anc| anc.p.is_finite() ), "{:?}", backend );
+        if backend == SolverBackend::Relaxation {
+            assert!( csys.residual_report().degenerate.is_empty() );
+        }
+    }
+}
+
+#[test]
+fn measuring_an_angle_between_coincident_anchors_is_finite() {
+    let mut csys = ConstraintSystem::new();
+    let a = csys.add_anchor( Vec2::new( 0.0, 0.0 ) );
+    let b = csys.add_anchor( Vec2::new( 0.0, 0.0 ) );
+    let c = csys.add_anchor( Vec2::new( 20.0, 0.0 ) );
+
+    // no target, so it's the current angle, which has to be something
+    let id = csys.add_constraint_angle( a, b, c, None );
+    let Constraint::Angle( angle ) = &csys.constraints[ id ] else {
+        panic!( "not an angle" );
+    };
+    assert!( angle.target_angle.is_finite() );
+
+    let mut residuals = Vec::new();
+    csys.constraints[ id ].residuals( &csys.anchors, &mut residuals );
+    assert!( residuals.iter().all( |r| r.value.is_finite() && r.grad.iter().all( |(_, g)| g.is_finite() ) ) );
+}
@@ -1,7 +1,7 @@
 use bevy::{prelude::* };
 use bevy_vello::{ prelude::* };
 
-use constraints::{ Constraint, PinMode };
+use constraints::{ AngleConstraint, Constraint, DofState, ParallelOffsetConstraint, PinMode };
 
 use vello::peniko::Color;
 
@@ -44,6 +44,16 @@ pub fn render_diagram(mut query_scene: Query<(&mut Transform, &mut VelloScene)>,
     let c_select =Color::rgba8( 252, 194, 225, 255 );
     let c_ghost = Color::rgba8( 76, 73, 166, 255);
 
+    // under-constrained things keep the normal colors
+    let c_full = Color::rgba8( 118, 181, 143, 255 );
+    let c_over = Color::rgba8( 224, 96, 96, 255 );
+    let c_conflict = Color::rgba8( 255, 150, 40, 255 );
+    let dof_color = |state : DofState, c_under : Color| match state {
+        DofState::Under => c_under,
+        DofState::Full => c_full,
+        DofState::Over => c_over,
+    };
+
     // If align mode (holding shift), draw the align line
     if state.do_align_cursor {
         let stroke = kurbo::Stroke::new(1.0).with_dashes( 0.0, [ 1.0, 4.0 ]);
@@ -85,7 +95,7 @@ pub fn render_diagram(mut query_scene: Query<(&mut Transform, &mut VelloScene)>,
 
 
     // Draw anchors
-    for (ndx, anc) in floorplan.csys.anchors.iter().enumerate() {
+    for (ndx, anc) in floorplan.csys.anchors.iter() {
 
         let radius = match state.hover_anchor {
             Some(hover_ndx) if hover_ndx == ndx => 8.0,
@@ -95,7 +105,7 @@ pub fn render_diagram(mut query_scene: Query<(&mut Transform, &mut VelloScene)>,
         let acolor = if state.selected_anchors.contains( &ndx ) {
             c_select
         } else {
-            c_walls
+            dof_color( state.dof_report.anchor_state( ndx ), c_walls )
         };
 
         // Draw crosshairs for pinned anchors
@@ -129,7 +139,14 @@ pub fn render_diagram(mut query_scene: Query<(&mut Transform, &mut VelloScene)>,
     // Draw constraints
     let stroke_cons = kurbo::Stroke::new(2.5);
     let stroke_cons_dashed = kurbo::Stroke::new(2.0).with_dashes( 0.0, [ 2.0, 5.0 ]);
-    for cons in floorplan.csys.constraints.iter() {
+    let mut equal_len_group = 0;
+    for (cons_id, cons) in floorplan.csys.constraints.iter() {
+
+        let c_constraint = if state.conflicts.contains( &cons_id ) || state.solve_report.degenerate.contains( &cons_id ) {
+            c_conflict
+        } else {
+            dof_color( state.dof_report.constraint_state( cons_id ), c_constraint )
+        };
 
         match cons {
             Constraint::FixedLength( fixed_len ) => {
@@ -165,23 +182,207 @@ pub fn render_diagram(mut query_scene: Query<(&mut Transform, &mut VelloScene)>,
 
             }
 
+            Constraint::ParallelOffset( offset ) => {
+
+                let pa = floorplan.csys.anchors[ offset.anc_a ].p;
+                let pb = floorplan.csys.anchors[ offset.anc_b ].p;
+                let pc = floorplan.csys.anchors[ offset.anc_c ].p;
+                let pd = floorplan.csys.anchors[ offset.anc_d ].p;
+
+                draw_constraint_parr( &mut scene, stroke_cons.clone(), c_constraint, pa, pb );
+                draw_constraint_parr( &mut scene, stroke_cons.clone(), c_constraint, pc, pd );
+
+                // dimension from the middle of AB straight across to CD
+                let start = (pa + pb) * 0.5;
+                let n = (pb - pa).normalize_or_zero().perp();
+                let end = start + n * ParallelOffsetConstraint::offset( pa, pb, pc, pd );
+                draw_constraint_dimension( &mut scene, stroke_pin.clone(), c_constraint, start, end, 0.0 );
+            }
+
+            Constraint::Perpendicular( perp ) => {
+
+                let pa = floorplan.csys.anchors[ perp.anc_a ].p;
+                let pb = floorplan.csys.anchors[ perp.anc_b ].p;
+                let pc = floorplan.csys.anchors[ perp.anc_c ].p;
+                let pd = floorplan.csys.anchors[ perp.anc_d ].p;
+
+                draw_constraint_perp( &mut scene, stroke_cons.clone(), c_constraint, pa, pb );
+                draw_constraint_perp( &mut scene, stroke_cons.clone(), c_constraint, pc, pd );
+            }
+
+            Constraint::Horizontal( horiz ) => {
+
+                let pa = floorplan.csys.anchors[ horiz.anc_a ].p;
+                let pb = floorplan.csys.anchors[ horiz.anc_b ].p;
+
+                // H glyph next to the wall
+                let ctr = (pa + pb) * 0.5 + Vec2::new( 0.0, 10.0 );
+                let mut path = kurbo::BezPath::new();
+                path.move_to( (ctr + Vec2::new( -3.0, 4.0 )).diagp() );
+                path.line_to( (ctr + Vec2::new( -3.0, -4.0 )).diagp() );
+                path.move_to( (ctr + Vec2::new( 3.0, 4.0 )).diagp() );
+                path.line_to( (ctr + Vec2::new( 3.0, -4.0 )).diagp() );
+                path.move_to( (ctr + Vec2::new( -3.0, 0.0 )).diagp() );
+                path.line_to( (ctr + Vec2::new( 3.0, 0.0 )).diagp() );
+                scene.stroke(&stroke_pin, kurbo::Affine::IDENTITY,
+                            c_constraint, None, &path);
+            }
+
+            Constraint::Vertical( vert ) => {
+
+                let pa = floorplan.csys.anchors[ vert.anc_a ].p;
+                let pb = floorplan.csys.anchors[ vert.anc_b ].p;
+
+                // V glyph next to the wall
+                let ctr = (pa + pb) * 0.5 + Vec2::new( 10.0, 0.0 );
+                let mut path = kurbo::BezPath::new();
+                path.move_to( (ctr + Vec2::new( -3.0, 4.0 )).diagp() );
+                path.line_to( (ctr + Vec2::new( 0.0, -4.0 )).diagp() );
+                path.line_to( (ctr + Vec2::new( 3.0, 4.0 )).diagp() );
+                scene.stroke(&stroke_pin, kurbo::Affine::IDENTITY,
+                            c_constraint, None, &path);
+            }
+
+            Constraint::EqualLength( equal_len ) => {
+
+                // each group gets its own tally of ticks, so you can tell which walls match
+                let group = equal_len_group;
+                equal_len_group += 1;
+
+                for (anc_a, anc_b) in equal_len.pairs.iter() {
+                    let pa = floorplan.csys.anchors[ *anc_a ].p;
+                    let pb = floorplan.csys.anchors[ *anc_b ].p;
+                    draw_constraint_ticks( &mut scene, stroke_pin.clone(), c_constraint, pa, pb, group );
+                }
+            }
+
+            Constraint::PointOnSegment( on_seg ) => {
+
+                // ring around the junction anchor
+                let pp = floorplan.csys.anchors[ on_seg.anc_p ].p;
+                scene.stroke(&stroke_pin, kurbo::Affine::IDENTITY,
+                            c_constraint, None, &kurbo::Circle::new( pp.diagp(), 9.0 ));
+            }
+
+            Constraint::Measurement( measurement ) => {
+
+                let pa = floorplan.csys.anchors[ measurement.anc_a ].p;
+                let pb = floorplan.csys.anchors[ measurement.anc_b ].p;
+
+                // thin dimension line with end stops, measurements the survey
+                // doesn't believe are drawn like conflicts
+                let suspect = state.survey_report.measurements.iter()
+                    .any( |m| m.constraint == cons_id && m.suspect() );
+                let c_measure = if suspect { c_conflict } else { c_constraint };
+                draw_constraint_dimension( &mut scene, stroke_pin.clone(), c_measure, pa, pb, 8.0 );
+            }
+
+            Constraint::LengthRange( len_range ) => {
+
+                // dashed dimension line, on the other side from measurements
+                let pa = floorplan.csys.anchors[ len_range.anc_a ].p;
+                let pb = floorplan.csys.anchors[ len_range.anc_b ].p;
+                draw_constraint_dimension( &mut scene, stroke_cons_dashed.clone(), c_constraint, pa, pb, -8.0 );
+            }
+
+            Constraint::AngleRange( ang_range ) => {
+
+                // arc over the allowed wedge, same orientation as the angle constraint's
+                let pa = floorplan.csys.anchors[ ang_range.anc_a ].p;
+                let pb = floorplan.csys.anchors[ ang_range.anc_b ].p;
+
+                let ba = pa - pb;
+                let start = ba.y.atan2( ba.x ) as f64 + ang_range.min_angle as f64;
+                let sweep = ang_range.span() as f64;
+                let arc = kurbo::Arc::new( pb.diagp(), (22.0, 22.0), -start, -sweep, 0.0 );
+                scene.stroke(&stroke_cons_dashed, kurbo::Affine::IDENTITY,
+                            c_constraint, None, &arc);
+            }
+
+            Constraint::Collinear( collinear ) => {
+
+                // faint hairline alongside the whole run, ticked at the anchors in between
+                let anchors = &floorplan.csys.anchors;
+                let [first, middle @ .., last] = collinear.anchors.as_slice() else {
+                    continue;
+                };
+                let pa = anchors[ *first ].p;
+                let pb = anchors[ *last ].p;
+                let perp = (pb - pa).normalize_or_zero().perp();
+
+                let mut path = kurbo::BezPath::new();
+                path.move_to( (pa + perp * 5.0).diagp() );
+                path.line_to( (pb + perp * 5.0).diagp() );
+                for anc in middle.iter() {
+                    let p = anchors[ *anc ].p;
+                    path.move_to( (p + perp * 3.0).diagp() );
+                    path.line_to( (p + perp * 7.0).diagp() );
+                }
+                scene.stroke(&kurbo::Stroke::new( 1.0 ), kurbo::Affine::IDENTITY,
+                    c_constraint.with_alpha_factor( 0.5 ), None, &path);
+            }
+
+            Constraint::Rigid( rigid ) => {
+
+                // the shape's bounding box, padded out a bit, turned to where the group is
+                let (rot, shape_center, center) = rigid.placement( &floorplan.csys.anchors );
+                let (lo, hi) = rigid.shape.iter().fold( (Vec2::MAX, Vec2::MIN), |(lo, hi), s| {
+                    (lo.min( *s - shape_center ), hi.max( *s - shape_center ))
+                });
+                let (lo, hi) = (lo - Vec2::splat( 12.0 ), hi + Vec2::splat( 12.0 ));
+
+                let mut path = kurbo::BezPath::new();
+                for (ndx, corner) in [ lo, Vec2::new( hi.x, lo.y ), hi, Vec2::new( lo.x, hi.y ) ].iter().enumerate() {
+                    let p = (rot.rotate( *corner ) + center).diagp();
+                    if ndx == 0 { path.move_to( p ) } else { path.line_to( p ) }
+                }
+                path.close_path();
+                scene.stroke(&stroke_cons_dashed, kurbo::Affine::IDENTITY,
+                    c_constraint, None, &path);
+            }
+
+            Constraint::Area( area ) => {
+
+                // tint the room, the area itself is labeled by the ui
+                let mut path = kurbo::BezPath::new();
+                for (ndx, anc) in area.anchors.iter().enumerate() {
+                    let p = floorplan.csys.anchors[ *anc ].p.diagp();
+                    if ndx == 0 { path.move_to( p ) } else { path.line_to( p ) }
+                }
+                path.close_path();
+                scene.fill( peniko::Fill::NonZero, kurbo::Affine::IDENTITY,
+                    c_constraint.with_alpha_factor( 0.15 ), None, &path );
+            }
+
+            Constraint::Symmetry( symmetry ) => {
+
+                // dashed axis, long enough to cover everything it mirrors
+                let (origin, dir) = symmetry.axis_line( &floorplan.csys.anchors );
+                let (t_min, t_max) = cons.anchors().iter()
+                    .map( |anc| dir.dot( floorplan.csys.anchors[ *anc ].p - origin ) )
+                    .fold( (0.0, 0.0), |(lo, hi) : (f32, f32), t| (lo.min( t ), hi.max( t )) );
+
+                let line = kurbo::Line::new( (origin + dir * (t_min - 20.0)).diagp(),
+                                             (origin + dir * (t_max + 20.0)).diagp() );
+                scene.stroke(&stroke_cons_dashed, kurbo::Affine::IDENTITY,
+                    c_constraint, None, &line);
+            }
+
             Constraint::Angle( angle ) => {
 
                 let pa = floorplan.csys.anchors[ angle.anc_a ].p;
                 let pb = floorplan.csys.anchors[ angle.anc_b ].p;
                 let pc = floorplan.csys.anchors[ angle.anc_c ].p;
 
-                let ba = (pa - pb).normalize() * 15.0;
-                let bc = (pc - pb).normalize() * 15.0;
-
-                let p2 = pb + ba + bc;
-                let line = kurbo::Line::new( (pb + ba).diagp(), p2.diagp() );
+                // arc sweeping counter-clockwise from BA to BC, so reflex corners
+                // get the arc on the outside. Diagram space is y-down, so the
+                // angles flip sign.
+                let ba = pa - pb;
+                let sweep = AngleConstraint::angle( pa, pb, pc ) as f64;
+                let start = ba.y.atan2( ba.x ) as f64;
+                let arc = kurbo::Arc::new( pb.diagp(), (15.0, 15.0), -start, -sweep, 0.0 );
                 scene.stroke(&stroke_cons, kurbo::Affine::IDENTITY,
-                            c_constraint, None, &line);
-
-                let line = kurbo::Line::new( (pb + bc).diagp(), p2.diagp() );
-                scene.stroke(&stroke_cons, kurbo::Affine::IDENTITY,
-                    c_constraint, None, &line);
+                            c_constraint, None, &arc);
 
             }
         }
@@ -211,7 +412,7 @@ pub fn render_diagram(mut query_scene: Query<(&mut Transform, &mut VelloScene)>,
 fn draw_constraint_parr( scene : &mut VelloScene, stroke_cons : kurbo::Stroke, brush : peniko::Color, pa : Vec2, pb : Vec2 )
 {
     let ctr = (pa + pb) * 0.5;
-    let ab = (pb -pa).normalize();
+    let ab = (pb -pa).normalize_or_zero();
     let perp = Vec2::new( ab.y, -ab.x ) * 5.0;
 
     let ab = ab * 5.0;
@@ -224,3 +425,65 @@ fn draw_constraint_parr( scene : &mut VelloScene, stroke_cons : kurbo::Stroke, b
         scene.stroke(&stroke_cons, kurbo::Affine::IDENTITY,
             brush, None, &line);
 }
+
+// Small right angle marker at the middle of the wall
+fn draw_constraint_perp( scene : &mut VelloScene, stroke_cons : kurbo::Stroke, brush : peniko::Color, pa : Vec2, pb : Vec2 )
+{
+    let ctr = (pa + pb) * 0.5;
+    let ab = (pb -pa).normalize_or_zero() * 6.0;
+    let perp = Vec2::new( ab.y, -ab.x );
+
+    let mut path = kurbo::BezPath::new();
+    path.move_to( (ctr + ab).diagp() );
+    path.line_to( (ctr + ab + perp).diagp() );
+    path.line_to( (ctr + perp).diagp() );
+
+    scene.stroke(&stroke_cons, kurbo::Affine::IDENTITY,
+        brush, None, &path);
+}
+
+// Tick marks across the middle of the wall, counted like a tally so every
+// group looks different: one to three short ticks, plus a long bar for
+// every three groups before it
+fn draw_constraint_ticks( scene : &mut VelloScene, stroke_cons : kurbo::Stroke, brush : peniko::Color, pa : Vec2, pb : Vec2, group : usize )
+{
+    let ctr = (pa + pb) * 0.5;
+    let ab = (pb -pa).normalize_or_zero();
+    let perp = Vec2::new( ab.y, -ab.x ) * 6.0;
+
+    let num_bars = group / 3;
+    let num_ticks = num_bars + (group % 3) + 1;
+
+    let mut path = kurbo::BezPath::new();
+    for i in 0..num_ticks {
+        let offs = ab * 3.0 * (i as f32 - (num_ticks - 1) as f32 * 0.5);
+        let len = if i < num_bars { 1.8 } else { 1.0 };
+        path.move_to( (ctr + offs + perp * len).diagp() );
+        path.line_to( (ctr + offs - perp * len).diagp() );
+    }
+
+    scene.stroke(&stroke_cons, kurbo::Affine::IDENTITY,
+        brush, None, &path);
+}
+
+// Dimension line with end stops, pushed side_offs to the side of AB so it
+// doesn't have to sit on top of a wall
+fn draw_constraint_dimension( scene : &mut VelloScene, stroke_cons : kurbo::Stroke, brush : peniko::Color, pa : Vec2, pb : Vec2, side_offs : f32 )
+{
+    let ab = (pb -pa).normalize_or_zero();
+    let perp = Vec2::new( ab.y, -ab.x );
+    let offs = perp * side_offs;
+
+    let mut path = kurbo::BezPath::new();
+    path.move_to( (pa + offs).diagp() );
+    path.line_to( (pb + offs).diagp() );
+
+    // end stops
+    for p in [ pa, pb ] {
+        path.move_to( (p + offs + perp * 4.0).diagp() );
+        path.line_to( (p + offs - perp * 4.0).diagp() );
+    }
+
+    scene.stroke(&stroke_cons, kurbo::Affine::IDENTITY,
+        brush, None, &path);
+}
@@ -1,40 +1,25 @@
 
 use bevy::{prelude::* };
-use constraints::{ ConstraintSystem };
+use constraints::{ AnchorId, Constraint, ConstraintSystem };
 
 // Ended up not using this
-#[derive(Copy,Clone)]
+#[derive(Copy,Clone,Default)]
 pub enum WallStyle
 {
+    #[default]
     Interior,
     Exterior
 }
 
-impl Default for WallStyle {
-    fn default() -> Self {
-        WallStyle::Interior
-    }
-}
 
-
-#[derive(Copy,Clone)]
+#[derive(Copy,Clone,Default)]
 pub struct Wall
 {
-    pub anchor_a : usize,
-    pub anchor_b : usize,
+    pub anchor_a : AnchorId,
+    pub anchor_b : AnchorId,
     pub _style : WallStyle,
 }
 
-impl Default for Wall {
-    fn default() -> Self {
-        Self {
-            anchor_a: 0,
-            anchor_b: 0,
-            _style: WallStyle::default(),
-        }
-    }
-}
-
 pub struct UndoCheckpoint {
     pub op_name : String,
     pub floorplan : Floorplan,
@@ -62,7 +47,7 @@ impl FloorplanUndoStack
     pub fn is_top_adjust( &self ) -> bool {
 
         // ?? the if let should cover this??
-        if self.stack.len() == 0 {
+        if self.stack.is_empty() {
             return false
         }
 
@@ -84,35 +69,70 @@ pub struct Floorplan
 impl Floorplan
 {
 
-    pub fn distance_to_wall( &self, wall_ndx : usize, p : Vec2 ) -> f32 {
+    pub fn closest_point_on_wall( &self, wall_ndx : usize, p : Vec2 ) -> Vec2 {
         let pa = self.csys.anchors[ self.walls[ wall_ndx ].anchor_a ].p;
         let pb = self.csys.anchors[ self.walls[ wall_ndx ].anchor_b ].p;
 
         let l2 = (pa - pb).length_squared();
         if  l2 < f32::EPSILON {
-            // endpoints the same, just return distance to the first
-            p.distance( pa )
+            // endpoints the same, just use the first
+            pa
         } else {
             let t = ((p - pa).dot( pb - pa ) / l2).clamp( 0.0, 1.0 );
-            let proj = pa + t * (pb - pa );
+            pa + t * (pb - pa )
+        }
+    }
+
+    pub fn distance_to_wall( &self, wall_ndx : usize, p : Vec2 ) -> f32 {
+        // distance to closest point on segment
+        self.closest_point_on_wall( wall_ndx, p ).distance( p )
+    }
 
-            // distance to closest point on segment
-            proj.distance( p )
+    // Finds the closest wall within 'threshold' distance
+    pub fn find_wall_at( &self, pos : Vec2, threshold : f32 ) -> Option<usize> {
+        let mut best_d = f32::MAX;
+        let mut closest_wall = None;
+        for ndx in 0..self.walls.len() {
+            let d = self.distance_to_wall( ndx, pos );
+            if (d < threshold) && (d < best_d) {
+                best_d = d;
+                closest_wall = Some( ndx )
+            }
         }
+        // result
+        closest_wall
     }
 
-    pub fn find_wall( &self, a : usize, b : usize ) -> Option<Wall> {
+    pub fn find_wall( &self, a : AnchorId, b : AnchorId ) -> Option<Wall> {
         self.walls.iter().find( |wall| {
             (wall.anchor_a == a && wall.anchor_b == b) ||
             (wall.anchor_a == b && wall.anchor_b == a)
         }).cloned()
     }
 
+    // Chains the walls end to end into a closed loop of anchors, e.g. the
+    // corners of a room. None if they don't make exactly one loop.
+    pub fn wall_loop( &self, walls : &[usize] ) -> Option<Vec<AnchorId>> {
+        let (&first, rest) = walls.split_first()?;
+        let mut remaining = rest.to_vec();
+        let mut corners = vec![ self.walls[ first ].anchor_a ];
+        let mut curr = self.walls[ first ].anchor_b;
+
+        while curr != corners[0] {
+            corners.push( curr );
+            let ndx = remaining.iter().position( |w| self.walls[ *w ].anchor_a == curr || self.walls[ *w ].anchor_b == curr )?;
+            let wall = self.walls[ remaining.remove( ndx ) ];
+            curr = if wall.anchor_a == curr { wall.anchor_b } else { wall.anchor_a };
+        }
+
+        (remaining.is_empty() && corners.len() >= 3).then_some( corners )
+    }
+
     // Finds the closest anchor within 'threshold' distance
-    pub fn find_anchor( &self, pos : Vec2, threshold : f32 ) -> Option<usize> {
+    pub fn find_anchor( &self, pos : Vec2, threshold : f32 ) -> Option<AnchorId> {
         let mut best_d = f32::MAX;
         let mut closest_anc = None;
-        for (ndx, anc) in self.csys.anchors.iter().enumerate() {
+        for (ndx, anc) in self.csys.anchors.iter() {
             let d = anc.p.distance(pos);
             if (d < threshold) && (d < best_d) {
                 closest_anc = Some(ndx);
@@ -123,6 +143,97 @@ impl Floorplan
         closest_anc
     }
 
+    // Removes an anchor, along with any walls and constraints that use it
+    pub fn remove_anchor( &mut self, id : AnchorId ) {
+        self.walls.retain( |wall| wall.anchor_a != id && wall.anchor_b != id );
+        self.csys.remove_anchor( id );
+    }
+
+    // Welds 'remove' into 'keep', rewiring the walls and constraints that used it.
+    // Walls that collapse to a point or end up duplicated are removed.
+    pub fn merge_anchors( &mut self, keep : AnchorId, remove : AnchorId ) {
+        if keep == remove {
+            return;
+        }
+
+        for wall in self.walls.iter_mut() {
+            if wall.anchor_a == remove { wall.anchor_a = keep; }
+            if wall.anchor_b == remove { wall.anchor_b = keep; }
+        }
+
+        let mut merged : Vec<Wall> = Vec::new();
+        for wall in self.walls.iter() {
+            let duplicate = merged.iter().any( |other| {
+                (other.anchor_a == wall.anchor_a && other.anchor_b == wall.anchor_b) ||
+                (other.anchor_a == wall.anchor_b && other.anchor_b == wall.anchor_a)
+            });
+            if wall.anchor_a != wall.anchor_b && !duplicate {
+                merged.push( *wall );
+            }
+        }
+        self.walls = merged;
+
+        self.csys.merge_anchors( keep, remove );
+    }
+
+    // Splits a wall in two with a new anchor at the closest point to pos. The
+    // halves are kept in a straight line, as part of the collinear run the wall
+    // was already in if there is one. The first half keeps the wall's index.
+    pub fn split_wall( &mut self, wall_ndx : usize, pos : Vec2 ) -> AnchorId {
+        let wall = self.walls[ wall_ndx ];
+        let (a, b) = (wall.anchor_a, wall.anchor_b);
+        let anc = self.csys.add_anchor( self.closest_point_on_wall( wall_ndx, pos ) );
+
+        self.walls[ wall_ndx ].anchor_b = anc;
+        self.walls.push( Wall { anchor_a : anc, anchor_b : b, ..wall } );
+
+        // the new anchor goes in between A and B if they're next to each other in a run
+        let run = self.csys.constraints.values_mut().find_map( |cons| {
+            let Constraint::Collinear( collinear ) = cons else {
+                return None;
+            };
+            let ndx = collinear.anchors.windows( 2 ).position( |w| (w[0] == a && w[1] == b) || (w[0] == b && w[1] == a) )?;
+            Some( (collinear, ndx) )
+        });
+
+        match run {
+            Some( (collinear, ndx) ) => {
+                collinear.anchors.insert( ndx + 1, anc );
+                self.csys.mark_changed();
+            }
+            None => { self.csys.add_constraint_collinear( &[ a, anc, b ] ); }
+        }
+
+        anc
+    }
+
+    // Removes walls by index. The anchors (and their constraints) stay around.
+    pub fn remove_walls( &mut self, wall_ndxs : &[usize] ) {
+        let mut wall_ndxs = wall_ndxs.to_vec();
+        wall_ndxs.sort_unstable();
+        wall_ndxs.dedup();
+
+        // remove from the back so the remaining indices stay valid
+        for ndx in wall_ndxs.into_iter().rev() {
+            self.walls.remove( ndx );
+        }
+    }
+
+    // Like find_anchor, but ignores 'exclude' (e.g. the anchor being dragged)
+    pub fn find_other_anchor( &self, pos : Vec2, threshold : f32, exclude : AnchorId ) -> Option<AnchorId> {
+        let mut best_d = f32::MAX;
+        let mut closest_anc = None;
+        for (ndx, anc) in self.csys.anchors.iter() {
+            let d = anc.p.distance(pos);
+            if ndx != exclude && (d < threshold) && (d < best_d) {
+                closest_anc = Some(ndx);
+                best_d = d;
+            }
+        }
+        // result
+        closest_anc
+    }
+
     pub fn make_starter_floorplan() -> Floorplan {
         let mut floorplan = Floorplan::default();
 
@@ -145,14 +256,74 @@ impl Floorplan
 
     pub fn copy_from ( &mut self, other : Floorplan )
     {
-        self.csys = other.csys.clone();
-        self.walls = other.walls.clone();
+        self.csys.copy_from( other.csys );
+        self.walls = other.walls;
     }
 
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removing_an_anchor_removes_its_walls() {
+        let mut floorplan = Floorplan::make_starter_floorplan();
+        let a = floorplan.walls[0].anchor_a;
+        let c = floorplan.walls[2].anchor_a;
+
+        floorplan.remove_anchor( a );
+        assert_eq!( floorplan.walls.len(), 2 );
+        assert!( floorplan.walls.iter().all( |wall| wall.anchor_a != a && wall.anchor_b != a ) );
+
+        // the fixed length on the wall from a is gone with it
+        assert!( floorplan.csys.constraints.is_empty() );
 
+        // removing walls leaves their anchors
+        floorplan.remove_walls( &[ 1, 0, 1 ] );
+        assert!( floorplan.walls.is_empty() );
+        assert!( floorplan.csys.anchors.contains_key( c ) );
+    }
+
+    #[test]
+    fn merging_anchors_drops_collapsed_and_duplicate_walls() {
+        let mut floorplan = Floorplan::make_starter_floorplan();
+        let a = floorplan.walls[0].anchor_a;
+        let b = floorplan.walls[1].anchor_a;
+        let c = floorplan.walls[2].anchor_a;
+        let d = floorplan.walls[3].anchor_a;
 
+        // a second wall from A to C, that ends up on top of B to C
+        floorplan.walls.push( Wall { anchor_a : c, anchor_b : a, ..default() } );
 
+        floorplan.merge_anchors( b, a );
+        assert!( !floorplan.csys.anchors.contains_key( a ) );
+
+        // A to B collapses, and C to A is now C to B which is already a wall
+        assert_eq!( floorplan.walls.len(), 3 );
+        for (p, q) in [ (b, c), (c, d), (d, b) ] {
+            assert!( floorplan.find_wall( p, q ).is_some() );
+        }
+    }
 
+    #[test]
+    fn splitting_a_wall_twice_makes_one_run() {
+        let mut floorplan = Floorplan::make_starter_floorplan();
+        let a = floorplan.walls[0].anchor_a;
+        let b = floorplan.walls[0].anchor_b;
+
+        let first = floorplan.split_wall( 0, Vec2::new( 50.0, -100.0 ) );
+        let second = floorplan.split_wall( 0, Vec2::new( -50.0, -100.0 ) );
+        assert_eq!( floorplan.walls.len(), 6 );
+        for (p, q) in [ (a, second), (second, first), (first, b) ] {
+            assert!( floorplan.find_wall( p, q ).is_some() );
+        }
 
+        // the second split goes into the run the first one started, in order
+        let runs : Vec<_> = floorplan.csys.constraints.values().filter_map( |cons| match cons {
+            Constraint::Collinear( collinear ) => Some( collinear.anchors.clone() ),
+            _ => None,
+        }).collect();
+        assert_eq!( runs, vec![ vec![ a, second, first, b ] ] );
+    }
+}
@@ -2,25 +2,25 @@ use core::f32;
 
 use bevy::{prelude::* };
 use bevy::input::mouse::MouseButtonInput;
+use bevy::tasks::Task;
+use bevy_egui::EguiContexts;
+
+use constraints::{ AnchorId, ConstraintId, DofReport, SecondaryMap, SolveReport, SurveyReport };
 
 use super::floorplan;
 use super::floorplan::FloorplanUndoStack;
 
 // This file contains interaction logic for dragging/selecting
 
-#[derive(Copy, Clone,PartialEq, Debug)]
+#[derive(Copy, Clone,PartialEq, Debug, Default)]
 pub enum InteractionMode {
+    #[default]
     Adjust,
     Create,
     SelectAnchors,
     SelectWalls,
     Preview,
 }
-impl Default for InteractionMode {
-    fn default() -> Self {
-        InteractionMode::Adjust
-    }
-}
 
 #[derive(Default)]
 pub struct CreateModeInteractionState {
@@ -28,8 +28,8 @@ pub struct CreateModeInteractionState {
     pub is_dragging: bool,
     pub drag_start: Vec2,
     pub drag_end: Vec2,
-    pub anc_start : Option<usize>,
-    pub anc_end : Option<usize>,
+    pub anc_start : Option<AnchorId>,
+    pub anc_end : Option<AnchorId>,
 }
 
 #[derive(Resource, Default)]
@@ -37,12 +37,12 @@ pub struct InteractionState {
     pub mode : InteractionMode,
     pub world_cursor : Vec2,
     pub world_cursor_align : Vec2,
-    pub hover_anchor : Option<usize>,
-    pub drag_anchor : Option<usize>,
+    pub hover_anchor : Option<AnchorId>,
+    pub drag_anchor : Option<AnchorId>,
 
     pub create : CreateModeInteractionState,
 
-    pub selected_anchors : Vec<usize>,
+    pub selected_anchors : Vec<AnchorId>,
     pub selected_walls : Vec<usize>,
 
     pub left_panel: f32,
@@ -52,11 +52,39 @@ pub struct InteractionState {
 
     // alternative solver mode
     pub solve_from_mousedown : bool,
-    pub anc_pos_mousedown: Vec<Vec2>,
+    pub anc_pos_mousedown: SecondaryMap<AnchorId, Vec2>,
+
+    // result of the last solve, for the status bar
+    pub solve_report : SolveReport,
+
+    // degrees of freedom left after the last solve, for coloring the diagram
+    pub dof_report : DofReport,
+
+    // revision of the constraint system the dof report is for
+    pub dof_revision : u64,
+    pub dof_task : Option<Task<DofReport>>,
+
+    // revision of the constraint system the reports are for
+    pub csys_revision : u64,
+
+    // minimal set of conflicting constraints, found in the background when a
+    // solve settles without converging
+    pub conflicts : Vec<ConstraintId>,
+    pub conflicts_task : Option<Task<Vec<ConstraintId>>>,
+
+    // revision of the constraint system the conflicts are for
+    pub conflicts_revision : u64,
+
+    // how far each tape measurement is from the plan, for the survey panel
+    pub survey_report : SurveyReport,
 }
 
 impl InteractionState {
 
+    pub fn has_selection( &self ) -> bool {
+        !self.selected_anchors.is_empty() || !self.selected_walls.is_empty()
+    }
+
     pub fn clear_selection( &mut self ) {
         self.hover_anchor = None;
         self.drag_anchor = None;
@@ -114,8 +142,8 @@ pub fn cursor_events(
         if state.drag_anchor.is_none() {
 
             // update the hover anchor if we're not currently dragging
-            let mut hover_anc:  Option<usize> = None;
-            for (ndx, anc) in floorplan.csys.anchors.iter().enumerate() {
+            let mut hover_anc:  Option<AnchorId> = None;
+            for (ndx, anc) in floorplan.csys.anchors.iter() {
                 if anc.p.distance(state.world_cursor) < 5.0 {
 
                     hover_anc = Some(ndx)
@@ -239,18 +267,7 @@ pub fn mouse_button_events(
 
                         let mut did_select = false;
 
-                        let mut best_d = f32::MAX;
-                        let mut closest_wall = None;
-                        for i in 0..floorplan.walls.len() {
-
-                            let d = floorplan.distance_to_wall( i, state.world_cursor );
-                            if (d < 5.0) && (d < best_d) {
-                                best_d = d;
-                                closest_wall = Some( i )
-                            }
-                        }
-
-                        if let Some(closest_wall) = closest_wall {
+                        if let Some(closest_wall) = floorplan.find_wall_at( state.world_cursor, 5.0 ) {
 
                             did_select = true;
 
@@ -294,32 +311,49 @@ pub fn mouse_button_events(
                 //         state.create.is_dragging = false;
                 //     }
                 // }
-                if ev.button == MouseButton::Left {
+                // Dropping an anchor onto another one welds them together
+                if ev.button == MouseButton::Left && state.mode == InteractionMode::Adjust {
+                    if let Some(drag_anchor) = state.drag_anchor {
+                        // a pinned anchor doesn't follow the cursor all the way, look where it actually is
+                        let drop_pos = floorplan.csys.anchors[ drag_anchor ].p;
+                        if let Some(target) = floorplan.find_other_anchor( drop_pos, 5.0, drag_anchor ) {
+                            undo.push_before_op( "Merge Anchors", &floorplan );
+                            floorplan.merge_anchors( target, drag_anchor );
+                            state.clear_selection();
+                        }
+                    }
+                    state.drag_anchor = None;
+                }
 
-                    if state.mode == InteractionMode::Create && state.create.is_dragging {
+                if ev.button == MouseButton::Left && state.mode == InteractionMode::Create && state.create.is_dragging {
 
-                        state.create.is_dragging = false;
-                        state.create.drag_end = state.world_cursor;
+                    state.create.is_dragging = false;
+                    state.create.drag_end = state.world_cursor;
 
-                        state.create.anc_end = floorplan.find_anchor( state.create.drag_end, 5.0);
+                    state.create.anc_end = floorplan.find_anchor( state.create.drag_end, 5.0);
 
-                        // Check minumum distance, otherwise just create an anchor
-                        if state.create.drag_start.distance( state.create.drag_end) < 10.0 {
+                    // Check minumum distance, otherwise just create an anchor
+                    if state.create.drag_start.distance( state.create.drag_end) < 10.0 {
 
-                            // just create an anchor if there isn't one there
-                            if state.create.anc_start.is_none() && state.create.anc_end.is_none() {
-                                let ctr = (state.create.drag_start + state.create.drag_end) * 0.5;
-                                println!("Create anchor {:?}", ctr );
+                        // just create an anchor if there isn't one there, clicking on
+                        // a wall splits it in two
+                        if state.create.anc_start.is_none() && state.create.anc_end.is_none() {
+                            let ctr = (state.create.drag_start + state.create.drag_end) * 0.5;
+                            if let Some( wall_ndx ) = floorplan.find_wall_at( ctr, 5.0 ) {
+                                undo.push_before_op( "Split Wall", &floorplan );
+                                floorplan.split_wall( wall_ndx, ctr );
+                            } else {
+                                debug!("Create anchor {:?}", ctr );
                                 let _new_anc = floorplan.csys.add_anchor( ctr );
                             }
+                        }
 
-                        } else {
+                    } else {
 
-                            undo.push_before_op( "Create Wall", &floorplan );
+                        undo.push_before_op( "Create Wall", &floorplan );
 
-                            // Create the wall
-                            create_wall( &mut floorplan, &state.create );
-                        }
+                        // Create the wall
+                        create_wall( &mut floorplan, &state.create );
                     }
                 }
             }
@@ -333,12 +367,22 @@ pub fn keyboard_input(
     keys: Res<ButtonInput<KeyCode>>,
     mut state : ResMut<InteractionState>,
     mut undo: ResMut<FloorplanUndoStack>,
+    mut contexts : EguiContexts,
 ) {
 
     // Hold shift to constrain cursor to horiz/vert
     state.do_align_cursor = (keys.pressed( KeyCode::ShiftLeft)) || (keys.pressed( KeyCode::ShiftRight));
     //println!("do_align_cursor: {}", state.do_align_cursor );
 
+    // the keys are for the text field or drag value being typed in, not the plan
+    if contexts.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+
+    // Delete the selected anchors or walls
+    if keys.just_pressed(KeyCode::Delete) {
+        delete_selection( &mut floorplan, &mut state, &mut undo );
+    }
 
     // Ctrl-Z undo
     if keys.just_pressed(KeyCode::KeyZ)  &&
@@ -352,20 +396,52 @@ pub fn keyboard_input(
 
 }
 
+pub fn delete_selection( floorplan : &mut floorplan::Floorplan, state : &mut InteractionState, undo : &mut FloorplanUndoStack )
+{
+    if !state.has_selection() {
+        return;
+    }
+
+    undo.push_before_op( "Delete", floorplan );
+
+    floorplan.remove_walls( &state.selected_walls );
+    for anc in state.selected_anchors.iter() {
+        floorplan.remove_anchor( *anc );
+    }
+
+    state.clear_selection();
+}
+
 fn create_wall( floorplan : &mut floorplan::Floorplan, create : &CreateModeInteractionState )
 {
 
     // Use or create an anchor for A
-    let anc_start = create.anc_start.unwrap_or_else(|| floorplan.csys.add_anchor( create.drag_start ) );
-    let anc_end = create.anc_end.unwrap_or_else(|| floorplan.csys.add_anchor( create.drag_end ) );
+    let anc_start = create.anc_start.unwrap_or_else(|| add_junction_anchor( floorplan, create.drag_start ) );
+    let anc_end = create.anc_end.unwrap_or_else(|| add_junction_anchor( floorplan, create.drag_end ) );
 
     // make sure wall doesn't already exist
     let existing_wall = floorplan.find_wall(anc_start, anc_end);
     if existing_wall.is_none() {
-        println!("Create wall {:?} {:?}", create.anc_start, create.anc_end  );
+        debug!("Create wall {:?} {:?}", create.anc_start, create.anc_end  );
         floorplan.walls.push( floorplan::Wall { anchor_a : anc_start, anchor_b : anc_end, ..default() });
     } else {
-        println!("Wall already exists {} {}", anc_start, anc_end  );
+        debug!("Wall already exists {:?} {:?}", anc_start, anc_end  );
     }
 
 }
+
+// Creates an anchor at pos. If that's on an existing wall, the anchor is snapped
+// onto it and kept there with a point-on-segment constraint (a T-junction). The
+// wall stays in one piece, clicking on it without dragging is what splits it.
+fn add_junction_anchor( floorplan : &mut floorplan::Floorplan, pos : Vec2 ) -> AnchorId
+{
+    let Some( wall_ndx ) = floorplan.find_wall_at( pos, 5.0 ) else {
+        return floorplan.csys.add_anchor( pos );
+    };
+
+    let wall = floorplan.walls[ wall_ndx ];
+    let anc = floorplan.csys.add_anchor( floorplan.closest_point_on_wall( wall_ndx, pos ) );
+    floorplan.csys.add_constraint_point_on_segment( anc, wall.anchor_a, wall.anchor_b, None );
+
+    anc
+}
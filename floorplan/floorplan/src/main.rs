@@ -9,6 +9,7 @@ use bevy_egui::{
     EguiPlugin};
 
 use bevy::diagnostic::FrameTimeDiagnosticsPlugin;
+use bevy::tasks::{ block_on, futures_lite::future, AsyncComputeTaskPool };
 
 use bevy_vello::{ prelude::*, VelloPlugin };
 
@@ -49,7 +50,7 @@ fn main() {
 
         .add_plugins(VelloPlugin::default())
         .add_plugins(EguiPlugin)
-        .add_plugins(FrameTimeDiagnosticsPlugin::default())
+        .add_plugins(FrameTimeDiagnosticsPlugin)
         .add_plugins(bevy_pancam::PanCamPlugin)
 
         // Main app systems
@@ -112,18 +113,20 @@ fn update_constraints(
 
         if state.solve_from_mousedown {
 
-            // resize and store current anchors (should happen on mousedown)
+            // store current anchors (should happen on mousedown)
             if state.anc_pos_mousedown.len() != floorplan.csys.anchors.len() {
-                state.anc_pos_mousedown.resize( floorplan.csys.anchors.len(), Vec2::ZERO );
+                state.anc_pos_mousedown.clear();
 
-                for i in 0..floorplan.csys.anchors.len() {
-                    state.anc_pos_mousedown[i] = floorplan.csys.anchors[i].p;
+                for (id, anc) in floorplan.csys.anchors.iter() {
+                    state.anc_pos_mousedown.insert( id, anc.p );
                 }
             }
 
             // Restore anchors from mousedown
-            for i in 0..floorplan.csys.anchors.len() {
-                floorplan.csys.anchors[i].p = state.anc_pos_mousedown[i];
+            for (id, anc) in floorplan.csys.anchors.iter_mut() {
+                if let Some( p ) = state.anc_pos_mousedown.get( id ) {
+                    anc.p = *p;
+                }
             }
         }
 
@@ -137,6 +140,7 @@ fn update_constraints(
                 _ => unreachable!(), // Don't try to drag fully pinned anchors
             }
         }
+        floorplan.csys.mark_changed();
         //println!("drag anchor is {}", drag_anchor );
     } else {
         // no drag anchor
@@ -144,8 +148,56 @@ fn update_constraints(
     }
 
 
-    // update the constraint solver
-    floorplan.csys.eval_system();
+    // update the constraint solver, but only if something changed since
+    // it last settled (or the whole plan was swapped out by undo etc)
+    if floorplan.csys.needs_solve() || floorplan.csys.revision() != state.csys_revision {
+        state.solve_report = floorplan.csys.eval_system();
+        state.survey_report = floorplan.csys.survey_report();
+        state.csys_revision = floorplan.csys.revision();
+
+        // forget the conflicts once they're resolved
+        if state.solve_report.converged {
+            state.conflicts.clear();
+            state.conflicts_task = None;
+        }
+    }
+
+    // The dof analysis is much slower than a solve, so it waits until the plan
+    // has settled down rather than running on every frame of a drag, and runs
+    // in the background like the conflict search below
+    if let Some( task ) = state.dof_task.as_mut() {
+        if let Some( dof_report ) = block_on( future::poll_once( task ) ) {
+            state.dof_task = None;
+            if state.dof_revision == state.csys_revision {
+                state.dof_report = dof_report;
+            }
+        }
+    }
+
+    if state.drag_anchor.is_none() && state.dof_revision != state.csys_revision && !floorplan.csys.needs_solve() {
+        let csys = floorplan.csys.clone();
+        state.dof_task = Some( AsyncComputeTaskPool::get().spawn( async move { csys.analyze_dof() } ) );
+        state.dof_revision = state.csys_revision;
+    }
+
+    // When a solve settles without converging, look for the constraints that are
+    // fighting each other. That's a solve for every constraint, so it's done in the
+    // background, starting over (and dropping the old search) if the plan changes.
+    if let Some( task ) = state.conflicts_task.as_mut() {
+        if let Some( conflicts ) = block_on( future::poll_once( task ) ) {
+            state.conflicts_task = None;
+            if state.conflicts_revision == state.csys_revision {
+                state.conflicts = conflicts;
+            }
+        }
+    }
+
+    if !state.solve_report.converged && state.drag_anchor.is_none() &&
+       state.conflicts_revision != state.csys_revision && !floorplan.csys.needs_solve() {
+        let csys = floorplan.csys.clone();
+        state.conflicts_task = Some( AsyncComputeTaskPool::get().spawn( async move { csys.find_conflicts() } ) );
+        state.conflicts_revision = state.csys_revision;
+    }
 }
 
 
@@ -186,7 +186,7 @@ pub fn rebuild_floorplan(
 
             let dir = pb - pa;
 
-            let dn = dir.normalize();
+            let dn = dir.normalize_or_zero();
             let ang = -dn.y.atan2( dn.x );
 
             let mut radius : f32 = 0.0;
@@ -209,7 +209,6 @@ pub fn rebuild_floorplan(
                         translation : Vec3::new( p.x, 0.0, p.y ),
                         rotation: Quat::from_rotation_y( ang ),
                         scale: Vec3::new( stretch * 1.1, 1.0, 1.0 ),
-                        ..default()
                     },
                     ..default()
                 }, PreviewGeo ));
@@ -220,7 +219,7 @@ pub fn rebuild_floorplan(
             }
 
             let mut pcam = camera_q.single_mut();
-            pcam.preview_radius = radius.max( 5.0 ).min( 20.0 );
+            pcam.preview_radius = radius.clamp( 5.0, 20.0 );
             //println!("Radius is {} pcam {}", radius, pcam.preview_radius );
         }
     }
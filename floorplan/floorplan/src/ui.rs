@@ -7,13 +7,13 @@ use bevy_egui::{
     //EguiPlugin
     };
 
-use constraints::{ Constraint, AnchorPoint, PinMode };
+use constraints::{ AnchorId, AngleConstraint, Constraint, ConstraintId, ConstraintSystem, AnchorPoint, PinMode, Priority, SlotMap, SolverBackend, SymmetryAxis, UpdateOrder };
 
 use crate::{floorplan::{Floorplan, FloorplanUndoStack}, preview::RebuildFloorplan};
 
 use super::floorplan;
 use super::preview;
-use super::interaction::{InteractionMode, InteractionState};
+use super::interaction::{self, InteractionMode, InteractionState};
 
 pub fn ui_example_system(
     mut contexts: EguiContexts,
@@ -21,9 +21,12 @@ pub fn ui_example_system(
     mut state: ResMut<InteractionState>,
     mut undo: ResMut<FloorplanUndoStack>,
     mut ev_rebuild: EventWriter<preview::RebuildFloorplan>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
 ) {
     let ctx = contexts.ctx_mut();
 
+    let c_conflict = egui::Color32::from_rgb( 255, 150, 40 );
+
     state.left_panel = egui::SidePanel::left("left_panel")
         .resizable(true)
         .show(ctx, |ui| {
@@ -122,6 +125,41 @@ pub fn ui_example_system(
                 }
             }
 
+            // Length range, starts off allowing 10% either way of the current length
+            if ui
+                .add_enabled(can_add_length_constraint,
+                    egui::widgets::Button::new("Length Range") )
+                .clicked()
+            {
+                let (a, b) = if state.mode == InteractionMode::SelectAnchors {
+                    (state.selected_anchors[0], state.selected_anchors[1])
+                } else {
+                    let wall = floorplan.walls[ state.selected_walls[0] ];
+                    (wall.anchor_a, wall.anchor_b)
+                };
+
+                let len = floorplan.csys.anchors[ a ].p.distance( floorplan.csys.anchors[ b ].p );
+                undo.push_before_op( "Length Range Constraint", &floorplan );
+                floorplan.csys.add_constraint_length_range( a, b, Some( len * 0.9 ), Some( len * 1.1 ) );
+            }
+
+            // Tape measurement, between the two ends of a wall or across a room
+            if ui
+                .add_enabled(can_add_length_constraint,
+                    egui::widgets::Button::new("Measurement") )
+                .clicked()
+            {
+                let (a, b) = if state.mode == InteractionMode::SelectAnchors {
+                    (state.selected_anchors[0], state.selected_anchors[1])
+                } else {
+                    let wall = floorplan.walls[ state.selected_walls[0] ];
+                    (wall.anchor_a, wall.anchor_b)
+                };
+
+                undo.push_before_op( "Measurement", &floorplan );
+                floorplan.csys.add_constraint_measurement( a, b, None, 1.0 );
+            }
+
             // Parallel walls
             let can_add_parallel_constraint = state.selected_walls.len() == 2;
             // TODO: check there is not already a constraint
@@ -141,8 +179,8 @@ pub fn ui_example_system(
                 let mut d = wall_b.anchor_b;
 
                 // see if AB -> CD or AB -> DC start off closer to parallel
-                let ab = (floorplan.csys.anchors[b].p - floorplan.csys.anchors[a].p).normalize();
-                let cd = (floorplan.csys.anchors[d].p - floorplan.csys.anchors[c].p).normalize();
+                let ab = (floorplan.csys.anchors[b].p - floorplan.csys.anchors[a].p).normalize_or_zero();
+                let cd = (floorplan.csys.anchors[d].p - floorplan.csys.anchors[c].p).normalize_or_zero();
                 if ab.dot( cd ) < 0.0 {
                     (d, c) = (c, d);
                 }
@@ -152,10 +190,181 @@ pub fn ui_example_system(
                 floorplan.csys.add_constraint_parallel( a,b,c,d );
             }
 
+            // Parallel walls at a fixed distance, e.g. both sides of a hallway
+            if ui
+                .add_enabled(can_add_parallel_constraint,
+                    egui::widgets::Button::new("Parallel Offset") )
+                .clicked()
+            {
+                let wall_a = floorplan.walls[ state.selected_walls[0] ];
+                let wall_b = floorplan.walls[ state.selected_walls[1] ];
+
+                let a = wall_a.anchor_a;
+                let b = wall_a.anchor_b;
+
+                // same as parallel, keep CD going the same way as AB
+                let mut c = wall_b.anchor_a;
+                let mut d = wall_b.anchor_b;
+                let ab = (floorplan.csys.anchors[b].p - floorplan.csys.anchors[a].p).normalize_or_zero();
+                let cd = (floorplan.csys.anchors[d].p - floorplan.csys.anchors[c].p).normalize_or_zero();
+                if ab.dot( cd ) < 0.0 {
+                    (d, c) = (c, d);
+                }
+
+                undo.push_before_op( "Parallel Offset Constraint", &floorplan );
+                floorplan.csys.add_constraint_parallel_offset( a, b, c, d, None );
+            }
+
+            // Perpendicular walls
+            let can_add_perpendicular_constraint = state.selected_walls.len() == 2;
+            // TODO: check there is not already a constraint
+            if ui
+                .add_enabled(can_add_perpendicular_constraint,
+                    egui::widgets::Button::new("Perpendicular") )
+                .clicked()
+            {
+                let wall_a = floorplan.walls[ state.selected_walls[0] ];
+                let wall_b = floorplan.walls[ state.selected_walls[1] ];
+
+                undo.push_before_op( "Perpendicular Constraint", &floorplan );
+                floorplan.csys.add_constraint_perpendicular( wall_a.anchor_a, wall_a.anchor_b,
+                                                             wall_b.anchor_a, wall_b.anchor_b );
+            }
+
+            // Horizontal/Vertical walls, applies to all the selected walls
+            let can_add_axis_constraint = state.mode == InteractionMode::SelectWalls && !state.selected_walls.is_empty();
+            ui.horizontal(|ui| {
+                // TODO: check there is not already a constraint
+                if ui
+                    .add_enabled(can_add_axis_constraint,
+                        egui::widgets::Button::new("Horizontal") )
+                    .clicked()
+                {
+                    undo.push_before_op( "Horizontal Constraint", &floorplan );
+                    for wall_ndx in state.selected_walls.iter() {
+                        let wall = floorplan.walls[ *wall_ndx ];
+                        floorplan.csys.add_constraint_horizontal( wall.anchor_a, wall.anchor_b );
+                    }
+                }
+
+                if ui
+                    .add_enabled(can_add_axis_constraint,
+                        egui::widgets::Button::new("Vertical") )
+                    .clicked()
+                {
+                    undo.push_before_op( "Vertical Constraint", &floorplan );
+                    for wall_ndx in state.selected_walls.iter() {
+                        let wall = floorplan.walls[ *wall_ndx ];
+                        floorplan.csys.add_constraint_vertical( wall.anchor_a, wall.anchor_b );
+                    }
+                }
+            });
+
+            // Symmetry, the first two anchors (or the first wall) are the axis and
+            // the rest are mirrored pairs
+            let symmetry = symmetry_selection( &floorplan, &state );
+            ui.horizontal(|ui| {
+                // TODO: check there is not already a constraint
+                if ui
+                    .add_enabled(symmetry.is_some(),
+                        egui::widgets::Button::new("Symmetric") )
+                    .clicked()
+                {
+                    let ((axis_a, axis_b), pairs) = symmetry.clone().unwrap();
+                    undo.push_before_op( "Symmetry Constraint", &floorplan );
+                    floorplan.csys.add_constraint_symmetry( SymmetryAxis::Anchors( axis_a, axis_b ), &pairs );
+                }
+
+                // same, but the axis stays where it is now
+                if ui
+                    .add_enabled(symmetry.is_some(),
+                        egui::widgets::Button::new("Symmetric (Fixed Axis)") )
+                    .clicked()
+                {
+                    let ((axis_a, axis_b), pairs) = symmetry.clone().unwrap();
+                    let point = floorplan.csys.anchors[ axis_a ].p;
+                    let dir = (floorplan.csys.anchors[ axis_b ].p - point).normalize_or_zero();
+                    undo.push_before_op( "Symmetry Constraint", &floorplan );
+                    floorplan.csys.add_constraint_symmetry( SymmetryAxis::Fixed { point, dir }, &pairs );
+                }
+            });
+
+            // Equal length walls
+            let can_add_equal_len_constraint = state.selected_walls.len() >= 2;
+            // TODO: check there is not already a constraint
+            if ui
+                .add_enabled(can_add_equal_len_constraint,
+                    egui::widgets::Button::new("Equal Length") )
+                .clicked()
+            {
+                let pairs : Vec<_> = state.selected_walls.iter().map( |wall_ndx| {
+                    let wall = floorplan.walls[ *wall_ndx ];
+                    (wall.anchor_a, wall.anchor_b)
+                }).collect();
+
+                undo.push_before_op( "Equal Length Constraint", &floorplan );
+                floorplan.csys.add_constraint_equal_len( &pairs );
+            }
+
+            // Straight run through the selected anchors, in order along the line
+            let can_add_collinear_constraint = state.mode == InteractionMode::SelectAnchors && state.selected_anchors.len() >= 3;
+            // TODO: check there is not already a constraint
+            if ui
+                .add_enabled(can_add_collinear_constraint,
+                    egui::widgets::Button::new("Collinear") )
+                .clicked()
+            {
+                // sort along the line between the two anchors furthest apart
+                let anchors = &floorplan.csys.anchors;
+                let sel = &state.selected_anchors;
+                let mut ends = (sel[0], sel[1]);
+                for (i, a) in sel.iter().enumerate() {
+                    for b in sel[ i + 1.. ].iter() {
+                        if anchors[ *a ].p.distance( anchors[ *b ].p ) > anchors[ ends.0 ].p.distance( anchors[ ends.1 ].p ) {
+                            ends = (*a, *b);
+                        }
+                    }
+                }
+                let origin = anchors[ ends.0 ].p;
+                let dir = anchors[ ends.1 ].p - origin;
+                let mut run = sel.clone();
+                run.sort_by( |a, b| dir.dot( anchors[ *a ].p - origin ).total_cmp( &dir.dot( anchors[ *b ].p - origin ) ) );
+
+                undo.push_before_op( "Collinear Constraint", &floorplan );
+                floorplan.csys.add_constraint_collinear( &run );
+            }
+
+            // Lock the selected anchors together, they can still move and turn as one
+            let can_add_rigid_constraint = state.mode == InteractionMode::SelectAnchors && state.selected_anchors.len() >= 3;
+            if ui
+                .add_enabled(can_add_rigid_constraint,
+                    egui::widgets::Button::new("Rigid Group") )
+                .clicked()
+            {
+                undo.push_before_op( "Rigid Group", &floorplan );
+                floorplan.csys.add_constraint_rigid( &state.selected_anchors );
+            }
+
+            // Room area, around the selected anchors in order or the loop of selected walls
+            let area_loop = match state.mode {
+                InteractionMode::SelectAnchors if state.selected_anchors.len() >= 3 => Some( state.selected_anchors.clone() ),
+                InteractionMode::SelectWalls => floorplan.wall_loop( &state.selected_walls ),
+                _ => None,
+            };
+            // TODO: check there is not already a constraint
+            if ui
+                .add_enabled(area_loop.is_some(),
+                    egui::widgets::Button::new("Area") )
+                .clicked()
+            {
+                undo.push_before_op( "Area Constraint", &floorplan );
+                floorplan.csys.add_constraint_area( &area_loop.unwrap(), None );
+            }
+
             // Fixed Angle
             let mut can_add_angle_constraint = state.selected_walls.len() == 2;
 
-            let mut shared_anchor =0;
+            let mut shared_anchor = AnchorId::default();
             if can_add_angle_constraint {
 
                 // make sure exactly one anchor is shared
@@ -169,7 +378,7 @@ pub fn ui_example_system(
                     wall_a.anchor_b
                 } else {
                     can_add_angle_constraint = false;
-                    0
+                    AnchorId::default()
                 };
             }
 
@@ -195,29 +404,53 @@ pub fn ui_example_system(
                     wall_b.anchor_a
                 };
 
-                let b = floorplan.csys.anchors[shared_anchor].p;
-                let b1 = (floorplan.csys.anchors[anc1].p - b).normalize();
-                let b2 = (b - floorplan.csys.anchors[anc2].p).normalize();
-                let ccw = b1.x*b2.y - b1.y*b2.x;
-                //println!("b1 cross b2 is {}", ccw );
-                let (anc1,anc2) = if ccw < 0.0 {
-                    (anc2, anc1)
-                } else {
-                    (anc1, anc2)
-                };
-
-                println!("make angle constraint for {} {} {}", anc1, shared_anchor, anc2 );
+                debug!("make angle constraint for {:?} {:?} {:?}", anc1, shared_anchor, anc2 );
                 undo.push_before_op( "Angle Constraint", &floorplan );
                 floorplan.csys.add_constraint_angle( anc1, shared_anchor, anc2,None);
             }
 
+            // Angle range, 15 degrees either way of the current angle
+            if ui
+                .add_enabled(can_add_angle_constraint,
+                    egui::widgets::Button::new("Angle Range") )
+                .clicked()
+            {
+                let wall_a = floorplan.walls[ state.selected_walls[0] ];
+                let wall_b = floorplan.walls[ state.selected_walls[1] ];
+
+                let anc1 = if wall_a.anchor_a == shared_anchor { wall_a.anchor_b } else { wall_a.anchor_a };
+                let anc2 = if wall_b.anchor_a == shared_anchor { wall_b.anchor_b } else { wall_b.anchor_a };
+
+                let anchors = &floorplan.csys.anchors;
+                let ang = AngleConstraint::angle( anchors[ anc1 ].p, anchors[ shared_anchor ].p, anchors[ anc2 ].p );
+                let slack = 15f32.to_radians();
+
+                undo.push_before_op( "Angle Range Constraint", &floorplan );
+                floorplan.csys.add_constraint_angle_range( anc1, shared_anchor, anc2, ang - slack, ang + slack );
+            }
+
+            ui.add(egui::Separator::default());
+
+            // Delete the selected anchors or walls (same as the Delete key)
+            if ui
+                .add_enabled(state.has_selection(),
+                    egui::widgets::Button::new("Delete Selected") )
+                .clicked()
+            {
+                interaction::delete_selection( &mut floorplan, &mut state, &mut undo );
+            }
+
             // Show panel for all selected anchors
             if state.mode == InteractionMode::SelectAnchors {
-                for (ndx, anc) in floorplan.csys.anchors.iter_mut().enumerate() {
+                let mut changed = false;
+                for (ndx, anc) in floorplan.csys.anchors.iter_mut() {
                     if state.selected_anchors.contains( &ndx ) {
-                        edit_anchor_panel( ui, anc );
+                        changed |= edit_anchor_panel( ui, anc );
                     }
                 }
+                if changed {
+                    floorplan.csys.mark_changed();
+                }
             }
 
             // Show panel for all constraints on the currently selected stuff
@@ -234,10 +467,44 @@ pub fn ui_example_system(
                 }
             }
 
-            for cons in floorplan.csys.constraints.iter_mut() {
+            if !state.conflicts.is_empty() {
+                ui.add(egui::Separator::default());
+                ui.colored_label( c_conflict, format!( "{} constraints conflict, delete or change one of them:",
+                    state.conflicts.len() ) );
+            } else if state.conflicts_task.is_some() {
+                ui.add(egui::Separator::default());
+                ui.label( "Looking for conflicting constraints..." );
+            }
+
+            let mut constraint_edit = None;
+            let mut changed = false;
+            let csys = &mut floorplan.csys;
+            for (cons_id, cons) in csys.constraints.iter_mut() {
+                let before = cons.clone();
+
+                // Conflicting constraints are always shown, highlighted
+                let edit = if state.conflicts.contains( &cons_id ) {
+                    let anchors = cons.anchors();
+                    ui.scope( |ui| {
+                        ui.visuals_mut().override_text_color = Some( c_conflict );
+                        edit_constraint_pane( ui, cons, &csys.anchors, &anchors )
+                    }).inner
+                } else {
+                    edit_constraint_pane( ui,  cons, &csys.anchors, &active_anchors )
+                };
+
+                if let Some( edit ) = edit {
+                    constraint_edit = Some( (cons_id, edit) );
+                }
+                changed |= *cons != before;
+            }
+            if changed {
+                csys.mark_changed();
+            }
 
-                // TODO: only show the constraints that have anchors or walls selected
-                edit_constraint_pane( ui,  cons, &active_anchors );
+            if let Some( (cons_id, edit) ) = constraint_edit {
+                undo.push_before_op( edit.op_name(), &floorplan );
+                apply_constraint_edit( &mut floorplan.csys, cons_id, edit );
             }
 
 
@@ -321,6 +588,83 @@ pub fn ui_example_system(
             // Solver settings
             ui.add(egui::Separator::default());
             ui.checkbox(&mut state.solve_from_mousedown, "Solve From Mousedown");
+            egui::CollapsingHeader::new( "Solver Settings" ).show( ui, |ui| {
+                let settings = &mut floorplan.csys.settings;
+                ui.horizontal(|ui| {
+                    ui.radio_value( &mut settings.backend, SolverBackend::Relaxation, "Relaxation" );
+                    ui.radio_value( &mut settings.backend, SolverBackend::LevenbergMarquardt, "Least Squares" );
+                });
+
+                ui.label( "Tolerance:" );
+                ui.add( egui::Slider::new( &mut settings.tolerance, 1e-5..=1e-1 ).logarithmic( true ) );
+
+                // these only affect relaxation
+                ui.add_enabled_ui( settings.backend == SolverBackend::Relaxation, |ui| {
+                    ui.label( "Max Iterations:" );
+                    ui.add( egui::Slider::new( &mut settings.max_iterations, 1..=500 ) );
+
+                    ui.label( "Damping:" );
+                    ui.add( egui::Slider::new( &mut settings.damping, 0.5..=0.999 ) );
+
+                    ui.horizontal(|ui| {
+                        ui.radio_value( &mut settings.order, UpdateOrder::GaussSeidel, "Gauss-Seidel" );
+                        ui.radio_value( &mut settings.order, UpdateOrder::Jacobi, "Jacobi" );
+                    });
+                    ui.checkbox( &mut settings.adaptive, "Adaptive Step" );
+                });
+
+                // and these only affect least squares
+                ui.add_enabled_ui( settings.backend == SolverBackend::LevenbergMarquardt, |ui| {
+                    ui.label( "Max Iterations:" );
+                    ui.add( egui::Slider::new( &mut settings.lm_max_iterations, 1..=200 ) );
+
+                    ui.label( "Starting Lambda:" );
+                    ui.add( egui::Slider::new( &mut settings.lm_lambda, 1e-6..=1e3 ).logarithmic( true ) )
+                        .on_hover_text( "Small takes bold Gauss-Newton steps, large takes cautious gradient descent steps" );
+                });
+            });
+
+            // Survey, fit the plan to the tape measurements
+            egui::CollapsingHeader::new( "Survey" ).show( ui, |ui| {
+                let has_measurements = !state.survey_report.measurements.is_empty();
+                if ui
+                    .add_enabled( has_measurements, egui::widgets::Button::new("Fit Survey") )
+                    .on_hover_text( "Fits with least squares whichever solver is chosen. The fit stays put until something is edited" )
+                    .clicked()
+                {
+                    undo.push_before_op( "Fit Survey", &floorplan );
+                    state.survey_report = floorplan.csys.fit_survey();
+                }
+
+                if !has_measurements {
+                    ui.label( "No measurements" );
+                    return;
+                }
+
+                let report = &state.survey_report;
+                ui.label( format!( "RMS {:.2} sigma", report.rms_normalized ) );
+
+                // worst first, so the one to re-measure is at the top
+                egui::Grid::new( "survey_residuals" ).striped( true ).show( ui, |ui| {
+                    ui.label( "Measured" );
+                    ui.label( "Fitted" );
+                    ui.label( "Sigmas" );
+                    ui.end_row();
+
+                    for m in report.measurements.iter() {
+                        let color = if m.suspect() { c_conflict } else { ui.visuals().text_color() };
+                        ui.colored_label( color, format!( "{:.1}", m.measured ) );
+                        ui.colored_label( color, format!( "{:.1}", m.fitted ) );
+                        ui.colored_label( color, format!( "{:+.2}", m.normalized ) );
+                        ui.end_row();
+                    }
+                });
+
+                if let Some( worst ) = report.worst().filter( |m| m.suspect() ) {
+                    ui.colored_label( c_conflict, format!( "Re-measure the {:.1}, it's off by {:+.1}",
+                        worst.measured, worst.residual ) );
+                }
+            });
 
 
 
@@ -355,13 +699,125 @@ pub fn ui_example_system(
     egui::TopBottomPanel::bottom("status_bar")
         .resizable(false)
         .show(ctx, |ui| {
-            ui.label("Status Bar");
+            let report = &state.solve_report;
+            let status = if report.converged {
+                format!( "Solved, max residual {:.4} ({} iterations)",
+                    report.max_residual, report.iterations )
+            } else {
+                format!( "Not converged, max residual {:.4}, rms {:.4} ({} iterations)",
+                    report.max_residual, report.rms_residual, report.iterations )
+            };
+            let dof = &state.dof_report;
+            ui.horizontal( |ui| {
+                ui.label( status );
+                ui.separator();
+                ui.label( format!( "{} degrees of freedom, {} redundant, {} conflicting",
+                    dof.dof, dof.num_redundant(), dof.num_conflicting() ) );
+
+                if !report.degenerate.is_empty() {
+                    ui.separator();
+                    ui.colored_label( c_conflict, format!( "{} constraints have anchors on top of each other",
+                        report.degenerate.len() ) );
+                }
+            });
             ui.allocate_rect(ui.available_rect_before_wrap(), egui::Sense::hover());
         });
+
+    if state.mode != InteractionMode::Preview {
+        let (cam, cam_transform) = q_camera.single();
+        draw_area_labels( ctx, cam, cam_transform, &floorplan, &state );
+    }
+}
+
+// Labels each room that has an area constraint with its area, drawn with egui
+// over the diagram since vello doesn't do text without a font
+fn draw_area_labels( ctx : &egui::Context, cam : &Camera, cam_transform : &GlobalTransform,
+                     floorplan : &Floorplan, state : &InteractionState )
+{
+    let c_label = egui::Color32::from_rgb( 188, 175, 171 );
+    let c_conflict = egui::Color32::from_rgb( 255, 150, 40 );
+
+    // keep off the panels
+    let painter = ctx.layer_painter( egui::LayerId::background() ).with_clip_rect( ctx.available_rect() );
+    let anchors = &floorplan.csys.anchors;
+    for (cons_id, cons) in floorplan.csys.constraints.iter() {
+        let Constraint::Area( cc_area ) = cons else {
+            continue;
+        };
+
+        let ctr = cc_area.anchors.iter().map( |a| anchors[ *a ].p ).sum::<Vec2>() / cc_area.anchors.len() as f32;
+        let Some( pos ) = cam.world_to_viewport( cam_transform, ctr.extend( 0.0 ) ) else {
+            continue;
+        };
+
+        let area = cc_area.area( anchors ).abs();
+        let target = cc_area.target_area.abs();
+        let (text, color) = if state.conflicts.contains( &cons_id ) || (area - target).abs() > target * 0.001 {
+            (format!( "{:.0} (target {:.0})", area, target ), c_conflict)
+        } else {
+            (format!( "{:.0}", area ), c_label)
+        };
+
+        painter.text( egui::pos2( pos.x, pos.y ), egui::Align2::CENTER_CENTER, text,
+                      egui::FontId::proportional( 14.0 ), color );
+    }
+}
+
+// The axis and mirrored pairs for a symmetry constraint, from the selection.
+// With anchors, the first two are the axis and the rest pair up in the order
+// they were selected. With walls, the first one is the axis and the rest pair
+// up, with the ends of each pair matched to whichever is closest to mirrored.
+type AnchorPair = (AnchorId, AnchorId);
+
+fn symmetry_selection( floorplan : &Floorplan, state : &InteractionState ) -> Option<(AnchorPair, Vec<AnchorPair>)>
+{
+    match state.mode {
+        InteractionMode::SelectAnchors => {
+            let sel = &state.selected_anchors;
+            if sel.len() < 4 || !sel.len().is_multiple_of( 2 ) {
+                return None;
+            }
+            let pairs = sel[2..].chunks( 2 ).map( |pair| (pair[0], pair[1]) ).collect();
+            Some( ((sel[0], sel[1]), pairs) )
+        }
+
+        InteractionMode::SelectWalls => {
+            let sel = &state.selected_walls;
+            if sel.len() < 3 || sel.len().is_multiple_of( 2 ) {
+                return None;
+            }
+
+            let anchors = &floorplan.csys.anchors;
+            let axis = floorplan.walls[ sel[0] ];
+            let origin = anchors[ axis.anchor_a ].p;
+            let n = (anchors[ axis.anchor_b ].p - origin).normalize_or_zero().perp();
+            let mirror = |p : Vec2| p - n * (2.0 * n.dot( p - origin ));
+
+            let mut pairs = Vec::new();
+            for walls in sel[1..].chunks( 2 ) {
+                let wall_a = floorplan.walls[ walls[0] ];
+                let wall_b = floorplan.walls[ walls[1] ];
+
+                let m = mirror( anchors[ wall_a.anchor_a ].p );
+                if m.distance( anchors[ wall_b.anchor_a ].p ) < m.distance( anchors[ wall_b.anchor_b ].p ) {
+                    pairs.push( (wall_a.anchor_a, wall_b.anchor_a) );
+                    pairs.push( (wall_a.anchor_b, wall_b.anchor_b) );
+                } else {
+                    pairs.push( (wall_a.anchor_a, wall_b.anchor_b) );
+                    pairs.push( (wall_a.anchor_b, wall_b.anchor_a) );
+                }
+            }
+            Some( ((axis.anchor_a, axis.anchor_b), pairs) )
+        }
+
+        _ => None,
+    }
 }
 
-fn edit_anchor_panel( ui: &mut egui::Ui, anchor : &mut AnchorPoint )
+// Returns true if the anchor was changed
+fn edit_anchor_panel( ui: &mut egui::Ui, anchor : &mut AnchorPoint ) -> bool
 {
+    let pin = anchor.pin;
     ui.add(egui::Separator::default());
 
     let mut pin_x = (anchor.pin==PinMode::PinX) || (anchor.pin==PinMode::PinXY);
@@ -380,10 +836,56 @@ fn edit_anchor_panel( ui: &mut egui::Ui, anchor : &mut AnchorPoint )
     } else {
         PinMode::Unpinned
     };
+    anchor.pin != pin
 }
 
-fn edit_constraint_pane( ui: &mut egui::Ui, constraint : &mut Constraint, active : &Vec<usize> )
+// Changes made from a constraint pane that get an undo checkpoint. They're applied
+// after all the panes are drawn, since the checkpoint needs the whole floorplan.
+enum ConstraintEdit
 {
+    Delete,
+    RemovePair( usize ), // from an equal length or symmetry constraint
+    Reshape,             // rigid group takes its current shape
+    SetPriority( Priority ),
+}
+
+impl ConstraintEdit
+{
+    fn op_name( &self ) -> &'static str {
+        match self {
+            ConstraintEdit::Delete => "Delete Constraint",
+            ConstraintEdit::RemovePair( _ ) => "Remove Pair",
+            ConstraintEdit::Reshape => "Reshape Rigid Group",
+            ConstraintEdit::SetPriority( _ ) => "Change Priority",
+        }
+    }
+}
+
+fn apply_constraint_edit( csys : &mut ConstraintSystem, cons_id : ConstraintId, edit : ConstraintEdit )
+{
+    if let ConstraintEdit::Delete = edit {
+        csys.remove_constraint( cons_id );
+        return;
+    }
+
+    let anchors = &csys.anchors;
+    match (&mut csys.constraints[ cons_id ], edit) {
+        (cons, ConstraintEdit::SetPriority( priority )) => { *cons.priority_mut() = priority; }
+        (Constraint::EqualLength( cc_equal ), ConstraintEdit::RemovePair( ndx )) => { cc_equal.pairs.remove( ndx ); }
+        (Constraint::Symmetry( cc_sym ), ConstraintEdit::RemovePair( ndx )) => { cc_sym.pairs.remove( ndx ); }
+        (Constraint::Rigid( cc_rigid ), ConstraintEdit::Reshape) => {
+            cc_rigid.shape = cc_rigid.anchors.iter().map( |a| anchors[ *a ].p ).collect();
+        }
+        _ => {}
+    }
+    csys.mark_changed();
+}
+
+// Returns the edit to make, if any buttons were pressed
+fn edit_constraint_pane( ui: &mut egui::Ui, constraint : &mut Constraint,
+                         anchors : &SlotMap<AnchorId, AnchorPoint>, active : &[AnchorId] ) -> Option<ConstraintEdit>
+{
+    let mut edit = None;
 
     match constraint {
 
@@ -391,7 +893,7 @@ fn edit_constraint_pane( ui: &mut egui::Ui, constraint : &mut Constraint, active
 
             // Is this constraint active in selected items?
             if !(active.contains( &cc_fixed.anc_a ) || active.contains( &cc_fixed.anc_b )) {
-                return;
+                return None;
             }
 
             ui.add(egui::Separator::default());
@@ -412,18 +914,269 @@ fn edit_constraint_pane( ui: &mut egui::Ui, constraint : &mut Constraint, active
             // Is this constraint active in selected items?
             if !(active.contains( &cc_parr.anc_a ) || active.contains( &cc_parr.anc_b ) ||
                  active.contains( &cc_parr.anc_c ) || active.contains( &cc_parr.anc_d )) {
-                return;
+                return None;
             }
 
             ui.add(egui::Separator::default());
             ui.label( "Parallel" );
         }
 
+        Constraint::ParallelOffset( cc_offset ) => {
+
+            if !(active.contains( &cc_offset.anc_a ) || active.contains( &cc_offset.anc_b ) ||
+                 active.contains( &cc_offset.anc_c ) || active.contains( &cc_offset.anc_d )) {
+                return None;
+            }
+
+            ui.add(egui::Separator::default());
+            ui.label( "Parallel Offset:" );
+
+            // the slider is the spacing, which side the other wall is on is kept separately.
+            // It goes up to the length of the wall, or further if it's already past that.
+            let side = if cc_offset.distance < 0.0 { -1.0 } else { 1.0 };
+            let mut spacing = cc_offset.distance.abs();
+            let wall_len = anchors[ cc_offset.anc_a ].p.distance( anchors[ cc_offset.anc_b ].p );
+            let top = f32::max( wall_len, spacing * 1.5 ).max( 1.0 );
+            ui.horizontal(|ui| {
+                if ui.add(egui::Slider::new( &mut spacing, 0.0..=top )).changed() {
+                    cc_offset.distance = spacing * side;
+                }
+                if ui.small_button( "Flip" ).clicked() {
+                    cc_offset.distance = -cc_offset.distance;
+                }
+            });
+        }
+
+        Constraint::Perpendicular( cc_perp ) => {
+
+            // Is this constraint active in selected items?
+            if !(active.contains( &cc_perp.anc_a ) || active.contains( &cc_perp.anc_b ) ||
+                 active.contains( &cc_perp.anc_c ) || active.contains( &cc_perp.anc_d )) {
+                return None;
+            }
+
+            ui.add(egui::Separator::default());
+            ui.label( "Perpendicular" );
+        }
+
+        Constraint::Horizontal( cc_horiz ) => {
+
+            if !(active.contains( &cc_horiz.anc_a ) || active.contains( &cc_horiz.anc_b )) {
+                return None;
+            }
+
+            ui.add(egui::Separator::default());
+            ui.label( "Horizontal" );
+        }
+
+        Constraint::Vertical( cc_vert ) => {
+
+            if !(active.contains( &cc_vert.anc_a ) || active.contains( &cc_vert.anc_b )) {
+                return None;
+            }
+
+            ui.add(egui::Separator::default());
+            ui.label( "Vertical" );
+        }
+
+        Constraint::EqualLength( cc_equal ) => {
+
+            if !cc_equal.pairs.iter().any( |(a, b)| active.contains( a ) || active.contains( b ) ) {
+                return None;
+            }
+
+            ui.add(egui::Separator::default());
+            ui.label( "Equal Length:" );
+
+            for (ndx, len) in cc_equal.lengths( anchors ).iter().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.label( format!( "{:.1}", len ) );
+
+                    // needs at least two pairs to mean anything
+                    if cc_equal.pairs.len() > 2 && ui.small_button( "Remove" ).clicked() {
+                        edit = Some( ConstraintEdit::RemovePair( ndx ) );
+                    }
+                });
+            }
+        }
+
+        Constraint::PointOnSegment( cc_on_seg ) => {
+
+            if !(active.contains( &cc_on_seg.anc_p ) || active.contains( &cc_on_seg.anc_a ) ||
+                 active.contains( &cc_on_seg.anc_b )) {
+                return None;
+            }
+
+            ui.add(egui::Separator::default());
+            ui.label( "Point On Wall" );
+
+            let mut fixed_ratio = cc_on_seg.ratio.is_some();
+            if ui.checkbox( &mut fixed_ratio, "Fixed Ratio" ).changed() {
+                cc_on_seg.ratio = if fixed_ratio {
+                    // start from wherever the point is now
+                    let pa = anchors[ cc_on_seg.anc_a ].p;
+                    let ab = anchors[ cc_on_seg.anc_b ].p - pa;
+                    let t = (anchors[ cc_on_seg.anc_p ].p - pa).dot( ab ) / ab.length_squared().max( f32::EPSILON );
+                    Some( t.clamp( 0.0, 1.0 ) )
+                } else {
+                    None
+                };
+            }
+
+            if let Some( ratio ) = cc_on_seg.ratio.as_mut() {
+                ui.add(egui::Slider::new( ratio, 0.0..=1.0 ));
+            }
+        }
+
+        Constraint::Measurement( cc_measure ) => {
+
+            if !(active.contains( &cc_measure.anc_a ) || active.contains( &cc_measure.anc_b )) {
+                return None;
+            }
+
+            ui.add(egui::Separator::default());
+            ui.label( format!( "Measurement (currently {:.1}):", cc_measure.length( anchors ) ) );
+            ui.horizontal(|ui| {
+                ui.add( egui::DragValue::new( &mut cc_measure.measured ).speed( 0.1 ).range( 0.0..=f32::MAX ) );
+                ui.label( "±" );
+                ui.add( egui::DragValue::new( &mut cc_measure.sigma ).speed( 0.01 ).range( 0.001..=100.0 ) );
+            });
+        }
+
+        Constraint::Symmetry( cc_sym ) => {
+
+            if !cc_sym.pairs.iter().any( |(a, b)| active.contains( a ) || active.contains( b ) ) &&
+               !matches!( cc_sym.axis, SymmetryAxis::Anchors( a, b ) if active.contains( &a ) || active.contains( &b ) ) {
+                return None;
+            }
+
+            ui.add(egui::Separator::default());
+            match cc_sym.axis {
+                SymmetryAxis::Anchors( .. ) => ui.label( "Symmetric:" ),
+                SymmetryAxis::Fixed { .. } => ui.label( "Symmetric (Fixed Axis):" ),
+            };
+
+            for (ndx, (a, b)) in cc_sym.pairs.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    let pa = anchors[ *a ].p;
+                    let pb = anchors[ *b ].p;
+                    ui.label( format!( "({:.0}, {:.0}) - ({:.0}, {:.0})", pa.x, pa.y, pb.x, pb.y ) );
+
+                    if cc_sym.pairs.len() > 1 && ui.small_button( "Remove" ).clicked() {
+                        edit = Some( ConstraintEdit::RemovePair( ndx ) );
+                    }
+                });
+            }
+        }
+
+        Constraint::Collinear( cc_col ) => {
+
+            if !cc_col.anchors.iter().any( |a| active.contains( a ) ) {
+                return None;
+            }
+
+            ui.add(egui::Separator::default());
+            ui.label( format!( "Collinear, {} anchors", cc_col.anchors.len() ) );
+        }
+
+        Constraint::Rigid( cc_rigid ) => {
+
+            if !cc_rigid.anchors.iter().any( |a| active.contains( a ) ) {
+                return None;
+            }
+
+            ui.add(egui::Separator::default());
+            ui.label( format!( "Rigid Group, {} anchors", cc_rigid.anchors.len() ) );
+
+            // take the shape the group is in now
+            if ui.small_button( "Reshape To Current" ).clicked() {
+                edit = Some( ConstraintEdit::Reshape );
+            }
+        }
+
+        Constraint::Area( cc_area ) => {
+
+            if !cc_area.anchors.iter().any( |a| active.contains( a ) ) {
+                return None;
+            }
+
+            ui.add(egui::Separator::default());
+            ui.label( format!( "Area (currently {:.0}):", cc_area.area( anchors ).abs() ) );
+
+            // edit the size, the sign just says which way round the loop goes
+            let side = if cc_area.target_area < 0.0 { -1.0 } else { 1.0 };
+            let mut target = cc_area.target_area.abs();
+            if ui.add( egui::DragValue::new( &mut target ).speed( 10.0 ).range( 0.0..=f32::MAX ) ).changed() {
+                cc_area.target_area = target * side;
+            }
+        }
+
+        Constraint::LengthRange( cc_range ) => {
+
+            if !(active.contains( &cc_range.anc_a ) || active.contains( &cc_range.anc_b )) {
+                return None;
+            }
+
+            ui.add(egui::Separator::default());
+            let len = anchors[ cc_range.anc_a ].p.distance( anchors[ cc_range.anc_b ].p );
+            ui.label( format!( "Length Range (currently {:.1}):", len ) );
+
+            // either end can be left open
+            let mut has_min = cc_range.min.is_some();
+            let mut has_max = cc_range.max.is_some();
+            ui.horizontal(|ui| {
+                ui.checkbox( &mut has_min, "Min" );
+                ui.checkbox( &mut has_max, "Max" );
+            });
+
+            let mut min = cc_range.min.unwrap_or( 0.0 );
+            let mut max = cc_range.max.unwrap_or( len );
+            if has_min && cc_range.min.is_none() { min = len.min( max ); }
+            if has_max && cc_range.max.is_none() { max = len.max( min ); }
+
+            // goes up to twice the current length, or further if the range already does
+            let top = f32::max( 2.0 * len, 1.5 * if has_max { max } else { min } ).max( 1.0 );
+            range_slider( ui, &mut min, &mut max, 0.0..=top, has_min, has_max, false );
+            ui.label( match (has_min, has_max) {
+                (true, true) => format!( "{:.1} to {:.1}", min, max ),
+                (true, false) => format!( "at least {:.1}", min ),
+                (false, true) => format!( "at most {:.1}", max ),
+                (false, false) => String::from( "no limits" ),
+            });
+
+            cc_range.min = has_min.then_some( min );
+            cc_range.max = has_max.then_some( max );
+        }
+
+        Constraint::AngleRange( cc_range ) => {
+
+            if !(active.contains( &cc_range.anc_a ) || active.contains( &cc_range.anc_b ) ||
+                 active.contains( &cc_range.anc_c )) {
+                return None;
+            }
+
+            ui.add(egui::Separator::default());
+            let ang = AngleConstraint::angle( anchors[ cc_range.anc_a ].p, anchors[ cc_range.anc_b ].p, anchors[ cc_range.anc_c ].p );
+            ui.label( format!( "Angle Range (currently {:.1}):", ang.to_degrees() ) );
+
+            // the range goes round from the min handle to the max handle, so with min
+            // past max it wraps through 0 (e.g. 350 to 10)
+            let mut min_deg = cc_range.min_angle.to_degrees();
+            let mut max_deg = cc_range.max_angle.to_degrees();
+            range_slider( ui, &mut min_deg, &mut max_deg, 0.0..=360.0, true, true, true );
+            ui.label( format!( "{:.1} to {:.1}", min_deg, max_deg ) );
+
+            if min_deg != cc_range.min_angle.to_degrees() || max_deg != cc_range.max_angle.to_degrees() {
+                cc_range.min_angle = min_deg.to_radians().rem_euclid( std::f32::consts::TAU );
+                cc_range.max_angle = max_deg.to_radians().rem_euclid( std::f32::consts::TAU );
+            }
+        }
+
         Constraint::Angle( cc_ang ) => {
 
             if !(active.contains( &cc_ang.anc_a ) || active.contains( &cc_ang.anc_b ) ||
                  active.contains( &cc_ang.anc_c )) {
-                    return;
+                    return None;
                  }
 
             let mut angle_deg = cc_ang.target_angle.to_degrees();
@@ -432,7 +1185,7 @@ fn edit_constraint_pane( ui: &mut egui::Ui, constraint : &mut Constraint, active
             if ui
                 .add(egui::Slider::new(
                     &mut angle_deg,
-                    3.0..=170.0,
+                    1.0..=359.0,
                 ))
                 .changed()
                 {
@@ -441,4 +1194,93 @@ fn edit_constraint_pane( ui: &mut egui::Ui, constraint : &mut Constraint, active
                 };
         }
     }
+
+    // only the constraints on the selection get this far
+    let mut priority = constraint.priority();
+    ui.horizontal( |ui| {
+        for p in Priority::ALL {
+            if ui.selectable_value( &mut priority, p, p.name() ).clicked() && p != constraint.priority() {
+                edit = Some( ConstraintEdit::SetPriority( p ) );
+            }
+        }
+    });
+
+    if ui.small_button( "Delete Constraint" ).clicked() {
+        edit = Some( ConstraintEdit::Delete );
+    }
+    edit
+}
+
+// Slider with a handle at each end of a range, dragging a handle past the
+// other one pushes it along. A handle that isn't enabled isn't drawn and
+// can't be grabbed. With wrap, the slider's ends are the same place (like
+// 0 and 360 degrees), the handles pass each other freely, and with min past
+// max the range runs off the right end and back in from the left.
+fn range_slider( ui : &mut egui::Ui, min : &mut f32, max : &mut f32, range : std::ops::RangeInclusive<f32>,
+                 min_enabled : bool, max_enabled : bool, wrap : bool ) -> egui::Response
+{
+    let width = ui.spacing().slider_width;
+    let height = ui.spacing().interact_size.y;
+    let (rect, response) = ui.allocate_exact_size( egui::vec2( width, height ), egui::Sense::click_and_drag() );
+
+    let radius = rect.height() * 0.3;
+    let track = rect.shrink2( egui::vec2( radius, 0.0 ) );
+    let (lo, hi) = (*range.start(), *range.end());
+    let to_x = |v : f32| egui::remap_clamp( v, lo..=hi, track.x_range() );
+    let to_value = |x : f32| egui::remap_clamp( x, track.x_range(), lo..=hi );
+
+    // grab whichever handle is closer when the drag starts, and hang on to it
+    let grab_id = response.id.with( "grabbed_max" );
+    if let Some( pos ) = response.interact_pointer_pos() {
+        if response.drag_started() || response.clicked() {
+            let grab_max = match (min_enabled, max_enabled) {
+                (true, true) => (pos.x - to_x( *max )).abs() < (pos.x - to_x( *min )).abs(),
+                (_, enabled) => enabled,
+            };
+            ui.data_mut( |d| d.insert_temp( grab_id, grab_max ) );
+        }
+
+        let value = to_value( pos.x );
+        let grabbed_max = ui.data( |d| d.get_temp( grab_id ).unwrap_or( false ) );
+        match (grabbed_max, wrap) {
+            (true, true) => *max = value,
+            (false, true) => *min = value,
+            (true, false) => {
+                *max = value;
+                *min = min.min( value );
+            }
+            (false, false) => {
+                *min = value;
+                *max = max.max( value );
+            }
+        }
+    }
+
+    if ui.is_rect_visible( rect ) {
+        let visuals = ui.style().interact( &response );
+        let painter = ui.painter();
+        let y = rect.center().y;
+
+        painter.line_segment( [ egui::pos2( track.left(), y ), egui::pos2( track.right(), y ) ],
+                              egui::Stroke::new( 2.0, ui.visuals().widgets.inactive.bg_fill ) );
+
+        // the allowed part of the range, in two pieces if it wraps round
+        let start = if min_enabled { to_x( *min ) } else { track.left() };
+        let end = if max_enabled { to_x( *max ) } else { track.right() };
+        let allowed = egui::Stroke::new( 4.0, ui.visuals().selection.bg_fill );
+        if wrap && start > end {
+            painter.line_segment( [ egui::pos2( start, y ), egui::pos2( track.right(), y ) ], allowed );
+            painter.line_segment( [ egui::pos2( track.left(), y ), egui::pos2( end, y ) ], allowed );
+        } else {
+            painter.line_segment( [ egui::pos2( start, y ), egui::pos2( end, y ) ], allowed );
+        }
+
+        for (value, enabled) in [ (*min, min_enabled), (*max, max_enabled) ] {
+            if enabled {
+                painter.circle( egui::pos2( to_x( value ), y ), radius, visuals.bg_fill, visuals.fg_stroke );
+            }
+        }
+    }
+
+    response
 }